@@ -1,4 +1,9 @@
 fn main() {
+    let pure_rust = std::env::var_os("CARGO_FEATURE_PURE_RUST").is_some();
+    let differential = std::env::var_os("CARGO_FEATURE_DIFFERENTIAL").is_some();
+    if pure_rust && !differential {
+        return;
+    }
     println!("cargo:rustc-link-search=native=Amazons");
     println!("cargo:rustc-link-lib=static=amazons");
 }
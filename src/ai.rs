@@ -0,0 +1,135 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::engine::AmazonsEngine;
+use crate::movegen::Move;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A computer player. Implementations only see the position through the
+/// rules engine, so any backend can be used.
+pub trait Ai<E: AmazonsEngine> {
+    /// Picks a turn for the player to move, or `None` if there is no legal
+    /// turn. The search reports its progress through `control` and returns
+    /// early once it is stopped.
+    fn choose_move(&mut self, engine: &E, control: &SearchControl) -> Option<Move>;
+}
+
+/// Shared state through which a running search can be stopped and observed
+#[derive(Debug, Clone, Default)]
+pub struct SearchControl {
+    stop: Arc<AtomicBool>,
+    nodes: Arc<AtomicU64>,
+}
+
+impl SearchControl {
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    /// Records that the search has visited more positions
+    pub fn add_nodes(&self, nodes: u64) {
+        self.nodes.fetch_add(nodes, Ordering::Relaxed);
+    }
+
+    pub fn nodes(&self) -> u64 {
+        self.nodes.load(Ordering::Relaxed)
+    }
+}
+
+/// A search running on a worker thread. Dropping the handle stops the search.
+#[derive(Debug)]
+pub struct SearchHandle {
+    control: SearchControl,
+    result: Receiver<Option<Move>>,
+    started: Instant,
+}
+
+impl SearchHandle {
+    /// Starts searching the given position on a new thread
+    pub fn spawn<A, E>(mut ai: A, engine: E) -> Self
+    where
+        A: Ai<E> + Send + 'static,
+        E: AmazonsEngine + Send + 'static,
+    {
+        let control = SearchControl::default();
+        let (sender, result) = mpsc::channel();
+        let worker = control.clone();
+        thread::spawn(move || {
+            let mv = ai.choose_move(&engine, &worker);
+            // The receiver is gone if the search was abandoned
+            let _ = sender.send(mv);
+        });
+        SearchHandle {
+            control,
+            result,
+            started: Instant::now(),
+        }
+    }
+
+    /// The chosen turn, once the search has finished. A search that ended
+    /// without a turn, including one whose thread panicked, gives `Some(None)`.
+    pub fn try_result(&self) -> Option<Option<Move>> {
+        match self.result.try_recv() {
+            Ok(mv) => Some(mv),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(None),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.control.stop();
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn nodes(&self) -> u64 {
+        self.control.nodes()
+    }
+}
+
+impl Drop for SearchHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alphabeta::AlphaBeta;
+    use crate::board::Board;
+
+    #[test]
+    fn cancelled_search_finishes() {
+        let ai = AlphaBeta::new(Duration::from_secs(600));
+        let handle = SearchHandle::spawn(ai, Board::standard());
+        thread::sleep(Duration::from_millis(50));
+        handle.cancel();
+        let start = Instant::now();
+        while handle.try_result().is_none() {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
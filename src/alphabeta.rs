@@ -0,0 +1,245 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Negamax search with alpha-beta pruning. The search deepens one turn at a
+//! time until the time budget runs out and plays the best turn of the deepest
+//! completed iteration. At the root, turns are tried in order of the scores
+//! from the previous iteration, the first of which comes from the static
+//! evaluation. Elsewhere they are ordered by a cheap guess from the squares
+//! around the destination and the arrow.
+
+use crate::ai::{Ai, SearchControl};
+use crate::board::{Cell, Coord, Player};
+use crate::engine::AmazonsEngine;
+use crate::eval::evaluate;
+use crate::movegen::{legal_moves, Move, DIRECTIONS};
+use std::time::{Duration, Instant};
+
+/// Score of a won game; wins found sooner score higher
+const WIN: i32 = 1_000_000;
+
+#[derive(Debug, Clone)]
+pub struct AlphaBeta {
+    pub time_budget: Duration,
+    /// Depth at which to stop even if time is left
+    pub max_depth: u32,
+    nodes: u64,
+    depth: u32,
+    deadline: Instant,
+    control: SearchControl,
+}
+
+impl AlphaBeta {
+    pub fn new(time_budget: Duration) -> Self {
+        AlphaBeta {
+            time_budget,
+            max_depth: 64,
+            nodes: 0,
+            depth: 0,
+            deadline: Instant::now(),
+            control: SearchControl::default(),
+        }
+    }
+
+    /// Number of positions visited by the last search
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    /// Depth of the last completed iteration of the last search
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    fn out_of_time(&self) -> bool {
+        Instant::now() >= self.deadline || self.control.is_stopped()
+    }
+
+    // Returns `None` if the time ran out before the search finished
+    fn negamax<E: AmazonsEngine + Clone>(
+        &mut self,
+        engine: &E,
+        depth: u32,
+        mut alpha: i32,
+        beta: i32,
+        ply: i32,
+    ) -> Option<i32> {
+        self.nodes += 1;
+        self.control.add_nodes(1);
+        if self.out_of_time() {
+            return None;
+        }
+        // A player who cannot move loses
+        if !engine.has_valid_move(engine.current_player()) {
+            return Some(-WIN + ply);
+        }
+        if depth == 0 {
+            return Some(evaluate(engine));
+        }
+        let mut moves = legal_moves(engine);
+        if depth > 1 {
+            let player = engine.current_player();
+            moves.sort_by_cached_key(|mv| -guess(engine, player, *mv));
+        }
+        let mut best = -WIN;
+        for mv in moves {
+            // Children are only built when they are reached, since most
+            // are cut off
+            let child = after(engine, mv);
+            let score = -self.negamax(&child, depth - 1, -beta, -alpha, ply + 1)?;
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        Some(best)
+    }
+}
+
+fn after<E: AmazonsEngine + Clone>(engine: &E, mv: Move) -> E {
+    let mut child = engine.clone();
+    mv.play(&mut child)
+        .expect("generated move rejected by the rules engine");
+    child
+}
+
+// Rough value of a turn for ordering, without playing it: room around the
+// amazon's new square plus opposing amazons hemmed in by the arrow
+fn guess<E: AmazonsEngine>(engine: &E, player: Player, mv: Move) -> i32 {
+    let neighbour = |c: Coord, (dx, dy): (i32, i32)| {
+        let (x, y) = (c.x as i64 + i64::from(dx), c.y as i64 + i64::from(dy));
+        if x < 0 || y < 0 {
+            return None;
+        }
+        let sq = Coord::new(x as u32, y as u32);
+        engine.cell(sq).ok().map(|cell| (sq, cell))
+    };
+    let mut score = 0;
+    for dir in DIRECTIONS {
+        if let Some((sq, cell)) = neighbour(mv.to, dir) {
+            if sq != mv.arrow && (cell == Cell::Empty || sq == mv.from) {
+                score += 1;
+            }
+        }
+        if let Some((_, Cell::Amazon(owner))) = neighbour(mv.arrow, dir) {
+            if owner != player {
+                score += 2;
+            }
+        }
+    }
+    score
+}
+
+impl<E: AmazonsEngine + Clone> Ai<E> for AlphaBeta {
+    fn choose_move(&mut self, engine: &E, control: &SearchControl) -> Option<Move> {
+        self.deadline = Instant::now() + self.time_budget;
+        self.control = control.clone();
+        self.nodes = 0;
+        self.depth = 0;
+        // Scoring the root is the same as searching one turn deep, so it
+        // counts as the first iteration if it finishes in time. On large
+        // boards it may not; turns not scored in time are tried last.
+        let mut finished = true;
+        let mut root: Vec<_> = legal_moves(engine)
+            .into_iter()
+            .map(|mv| {
+                let score = if finished {
+                    self.negamax(&after(engine, mv), 0, -WIN - 1, WIN + 1, 1)
+                } else {
+                    None
+                };
+                finished = score.is_some();
+                (mv, score.map_or(-WIN - 1, |score| -score))
+            })
+            .collect();
+        root.sort_by_key(|(_, score)| -score);
+        let mut best = root.first()?.0;
+        if finished {
+            self.depth = 1;
+        }
+        let decided = |score: i32| score.abs() > WIN / 2;
+        if !finished || decided(root[0].1) {
+            return Some(best);
+        }
+        for depth in 2..=self.max_depth {
+            let mut alpha = -WIN - 1;
+            let mut finished = true;
+            for (mv, score) in root.iter_mut() {
+                let child = after(engine, *mv);
+                match self.negamax(&child, depth - 1, -WIN - 1, -alpha, 1) {
+                    Some(child_score) => *score = -child_score,
+                    None => {
+                        finished = false;
+                        break;
+                    }
+                }
+                // Turns searched before running out of time are compared
+                // against the previous best, which is always searched first
+                if *score > alpha {
+                    alpha = *score;
+                    best = *mv;
+                }
+            }
+            if !finished {
+                break;
+            }
+            self.depth = depth;
+            root.sort_by_key(|(_, score)| -*score);
+            // Stop once the game is decided either way
+            if decided(alpha) {
+                break;
+            }
+        }
+        Some(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+
+    #[test]
+    fn finds_winning_turn() {
+        // White wins by ending the turn next to black, which is then stuck
+        let board = Board::new(4, 1, &[(0, 0)], &[(3, 0)]).unwrap();
+        let mut ai = AlphaBeta::new(Duration::from_secs(5));
+        let mv = ai.choose_move(&board, &SearchControl::default()).unwrap();
+        let mut after = board.clone();
+        mv.play(&mut after).unwrap();
+        assert!(!after.has_valid_move(after.current_player()));
+        assert!(ai.nodes() > 0);
+    }
+
+    #[test]
+    fn respects_time_budget() {
+        let board = Board::standard();
+        let mut ai = AlphaBeta::new(Duration::from_millis(200));
+        let start = Instant::now();
+        assert!(ai.choose_move(&board, &SearchControl::default()).is_some());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn root_scores_count_as_first_iteration() {
+        let board = Board::new(5, 5, &[(0, 0), (4, 0)], &[(0, 4), (4, 4)]).unwrap();
+        let mut ai = AlphaBeta::new(Duration::from_secs(5));
+        ai.max_depth = 1;
+        assert!(ai.choose_move(&board, &SearchControl::default()).is_some());
+        assert_eq!(ai.depth(), 1);
+        // Every root turn is scored once and none is searched again
+        assert_eq!(ai.nodes(), legal_moves(&board).len() as u64);
+    }
+}
@@ -13,31 +13,107 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use crate::boardstate::Amazons::*;
 use crate::sprites::*;
+use amazons::ai::SearchHandle;
+use amazons::alphabeta::AlphaBeta;
+use amazons::board::{Board, BoardError, Cell, Coord, Player};
+use amazons::engine::AmazonsEngine;
+use amazons::eval::{evaluate, Territory};
+use amazons::gtp::{ClientError, ExternalEngine};
+use amazons::history::History;
+use amazons::mcts::{Limit, Mcts};
+use amazons::movegen::{arrow_targets, check_line, check_source, destinations, Move};
+use amazons::net::{
+    Announcement, Announcer, Browser, Dialer, Event, GameStatus, Listener, LobbyGame, Message,
+    Session, DEFAULT_PORT, DISCOVERY_PORT,
+};
+use amazons::position::Position;
+use amazons::record::GameRecord;
+use amazons::sgf::SgfGame;
 use eframe::egui::emath::RectTransform;
 use eframe::egui::{
-    emath, Color32, Painter, Pos2, Rect, Sense, Separator, Slider, TextureId, Ui, Vec2,
+    emath, Button, Color32, DragValue, Key, Painter, Pos2, Rect, Sense, Separator, Slider,
+    TextureId, Ui, Vec2,
 };
 use eframe::epi::Frame;
 use eframe::{egui, epi};
+use std::time::{Duration, Instant};
 
 type PosVec = Vec<(u32, u32)>;
 type ImageData = (TextureId, Vec2);
 
+#[cfg(feature = "persistence")]
+const GAME_KEY: &str = "game";
+
+/// How long the reason for a rejected click stays in the side panel
+const RULE_MESSAGE_TIME: Duration = Duration::from_secs(4);
+
+/// The live game as stored between sessions: the game record along with
+/// the number of turns not undone and the squares selected in the current turn
+#[cfg(feature = "persistence")]
+#[derive(serde::Deserialize, serde::Serialize)]
+struct SavedGame {
+    record: String,
+    turns: usize,
+    clicked_square: u8,
+    src_square: (u32, u32),
+    dst_square: (u32, u32),
+    // The winner if the game was over, which the record alone cannot show
+    // when a side resigned or forfeited
+    #[serde(default)]
+    winner: Option<Player>,
+}
+
+/// Who plays one side of the game
+#[cfg_attr(feature = "persistence", derive(serde::Deserialize, serde::Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlayerKind {
+    Human,
+    AlphaBeta,
+    Mcts,
+    External,
+    /// The other side of a network game; never chosen in the settings
+    Remote,
+}
+
+impl PlayerKind {
+    const ALL: [PlayerKind; 4] = [
+        PlayerKind::Human,
+        PlayerKind::AlphaBeta,
+        PlayerKind::Mcts,
+        PlayerKind::External,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            PlayerKind::Human => "Human",
+            PlayerKind::AlphaBeta => "Computer (alpha-beta)",
+            PlayerKind::Mcts => "Computer (MCTS)",
+            PlayerKind::External => "External engine",
+            PlayerKind::Remote => "Network player",
+        }
+    }
+}
+
 #[derive(PartialEq)]
 enum ClickableState {
     GameInProgress,
     PickingWhite,
     PickingBlack,
-    GameOver(SquareState),
+    GameOver(Player),
     InvalidConfig(String),
     Idle,
+    // Choosing a network game to join
+    Joining,
+    // Hosting or joining a network game that has not started yet
+    Connecting,
+    // Choosing or creating a game on a server
+    Lobby,
 }
 
 #[cfg_attr(feature = "persistence", derive(serde::Deserialize, serde::Serialize))]
 #[cfg_attr(feature = "persistence", serde(default))]
-pub struct AmazonsGame {
+pub struct AmazonsGame<E: AmazonsEngine = Board> {
     // Board dimensions
     board_height: u32,
     board_width: u32,
@@ -50,6 +126,60 @@ pub struct AmazonsGame {
     white_starting: PosVec,
     black_starting: PosVec,
 
+    // Player names
+    white_name: String,
+    black_name: String,
+
+    // Computer players
+    white_player: PlayerKind,
+    black_player: PlayerKind,
+    ai_seconds: f32,
+    // MCTS searches a fixed number of playouts if set, otherwise for `ai_seconds`
+    mcts_playouts: Option<u32>,
+    mcts_exploration: f64,
+    // Engine programs used by external players
+    white_engine_path: String,
+    black_engine_path: String,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    white_engine: Option<ExternalEngine>,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    black_engine: Option<ExternalEngine>,
+    // Why an external engine was dropped from the game
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    engine_message: Option<String>,
+
+    // Network games
+    net_address: String,
+    net_port: u16,
+    server_address: String,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    listener: Option<Listener>,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    dialer: Option<Dialer>,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    announcer: Option<Announcer>,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    browser: Option<Browser>,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    session: Option<Session<E>>,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    net_message: Option<String>,
+    // Games listed by the server, and the one we opened if waiting for an opponent
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    lobby_games: Vec<LobbyGame>,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    created_game: Option<u32>,
+
+    // Game records
+    game_file: String,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    file_message: Option<String>,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    position_text: String,
+    // Tree of the last SGF file, kept so that saving preserves its variations
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    sgf: Option<SgfGame>,
+
     // Sprites
     #[cfg_attr(feature = "persistence", serde(skip))]
     white_sprite: Option<ImageData>,
@@ -62,7 +192,11 @@ pub struct AmazonsGame {
     #[cfg_attr(feature = "persistence", serde(skip))]
     state: ClickableState,
     #[cfg_attr(feature = "persistence", serde(skip))]
-    boardstate: BoardState,
+    board: E,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    history: History<E>,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    viewing: Option<usize>,
     #[cfg_attr(feature = "persistence", serde(skip))]
     white_squares: u32,
     #[cfg_attr(feature = "persistence", serde(skip))]
@@ -70,19 +204,32 @@ pub struct AmazonsGame {
 
     #[cfg_attr(feature = "persistence", serde(skip))]
     highlight_regions: bool,
+    // Shade the estimated territory while the game is in progress
+    show_territory: bool,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    search: Option<SearchHandle>,
+
+    // Turn suggested to the player to move
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    hint_search: Option<SearchHandle>,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    hint: Option<Move>,
 
     // User move
     #[cfg_attr(feature = "persistence", serde(skip))]
-    src_square: Square,
+    src_square: Coord,
     #[cfg_attr(feature = "persistence", serde(skip))]
-    dst_square: Square,
+    dst_square: Coord,
     #[cfg_attr(feature = "persistence", serde(skip))]
-    shot_square: Square,
+    shot_square: Coord,
     #[cfg_attr(feature = "persistence", serde(skip))]
     clicked_square: u8,
+    // Why the last click was rejected, and when
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    rule_message: Option<(String, Instant)>,
 }
 
-impl Default for AmazonsGame {
+impl<E: AmazonsEngine + Clone + Default> Default for AmazonsGame<E> {
     fn default() -> Self {
         AmazonsGame {
             board_height: 10,
@@ -91,23 +238,57 @@ impl Default for AmazonsGame {
             black_amazons: 4,
             white_starting: vec![],
             black_starting: vec![],
+            white_name: "Player 1".to_string(),
+            black_name: "Player 2".to_string(),
+            white_player: PlayerKind::Human,
+            black_player: PlayerKind::Human,
+            ai_seconds: 2.,
+            mcts_playouts: None,
+            mcts_exploration: 1.4,
+            white_engine_path: String::new(),
+            black_engine_path: String::new(),
+            white_engine: None,
+            black_engine: None,
+            engine_message: None,
+            net_address: "127.0.0.1".to_string(),
+            net_port: DEFAULT_PORT,
+            server_address: "127.0.0.1".to_string(),
+            listener: None,
+            dialer: None,
+            announcer: None,
+            browser: None,
+            session: None,
+            net_message: None,
+            lobby_games: vec![],
+            created_game: None,
+            game_file: "amazons.txt".to_string(),
+            file_message: None,
+            position_text: String::new(),
+            sgf: None,
             white_sprite: None,
             black_sprite: None,
             arrow_sprite: None,
             state: ClickableState::Idle,
-            boardstate: BoardState::default(),
+            board: E::default(),
+            history: History::default(),
+            viewing: None,
             white_squares: 0,
             black_squares: 0,
             highlight_regions: false,
-            src_square: Square::default(),
-            dst_square: Square::default(),
-            shot_square: Square::default(),
+            show_territory: false,
+            search: None,
+            hint_search: None,
+            hint: None,
+            src_square: Coord::default(),
+            dst_square: Coord::default(),
+            shot_square: Coord::default(),
             clicked_square: 0,
+            rule_message: None,
         }
     }
 }
 
-impl AmazonsGame {
+impl<E: AmazonsEngine + Clone + Default> AmazonsGame<E> {
     fn is_empty_config(&self) -> bool {
         self.white_starting.is_empty()
             && self.white_amazons == 4
@@ -118,6 +299,12 @@ impl AmazonsGame {
     }
 
     fn config_is_valid(&self) -> Result<(), &str> {
+        if self.white_player == PlayerKind::External && self.white_engine_path.trim().is_empty() {
+            return Err("No engine chosen for player 1");
+        }
+        if self.black_player == PlayerKind::External && self.black_engine_path.trim().is_empty() {
+            return Err("No engine chosen for player 2");
+        }
         if self.is_empty_config() {
             return Ok(());
         }
@@ -143,25 +330,37 @@ impl AmazonsGame {
         Ok(())
     }
 
-    pub fn new_game(&mut self) {
-        if let Err(e) = self.config_is_valid() {
-            self.state = ClickableState::InvalidConfig(e.to_string());
-            return;
-        }
+    fn starting_board(&self) -> Result<E, String> {
+        self.config_is_valid().map_err(str::to_string)?;
         if self.white_starting.is_empty() {
-            unsafe {
-                boardstate_standard(&mut self.boardstate);
-            }
-        } else {
-            self.boardstate.init(
-                self.white_amazons,
-                self.black_amazons,
-                self.board_width,
-                self.board_height,
-                &self.white_starting,
-                &self.black_starting,
-            );
+            return Ok(E::standard());
         }
+        E::new(
+            self.board_width,
+            self.board_height,
+            &self.white_starting,
+            &self.black_starting,
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    pub fn new_game(&mut self) {
+        match self.starting_board() {
+            Ok(board) => self.start_game(board),
+            Err(e) => self.state = ClickableState::InvalidConfig(e),
+        }
+    }
+
+    fn start_game(&mut self, board: E) {
+        self.board = board;
+        self.history = History::new(self.board.clone());
+        self.cancel_search();
+        self.stop_engines();
+        self.clear_hint();
+        self.sgf = None;
+        self.viewing = None;
+        self.clicked_square = 0;
+        self.net_message = None;
         self.state = ClickableState::GameInProgress;
     }
 
@@ -179,8 +378,20 @@ impl AmazonsGame {
         self.arrow_sprite = Some(arrows);
     }
 
+    // Dimensions of the board on screen: the game being shown, which need not
+    // match the settings when it was joined or loaded, or else the settings
+    fn board_size(&self) -> (u32, u32) {
+        match self.state {
+            ClickableState::GameInProgress | ClickableState::GameOver(_) => {
+                (self.board.width(), self.board.height())
+            }
+            _ => (self.board_width, self.board_height),
+        }
+    }
+
     fn square_size(&self) -> f32 {
-        (1. / self.board_height as f32).min(1. / self.board_width as f32)
+        let (width, height) = self.board_size();
+        (1. / height as f32).min(1. / width as f32)
     }
 
     fn square_from_coords(&self, x: u32, y: u32, to_screen: RectTransform) -> Rect {
@@ -208,13 +419,13 @@ impl AmazonsGame {
         painter.add(egui::Shape::mesh(mesh));
     }
 
-    #[allow(non_upper_case_globals)]
     fn draw_board(&mut self, painter: &Painter, to_screen: RectTransform, frame: &Frame) {
         if self.white_sprite.is_none() {
             self.load_sprites(frame)
         }
-        for x in 0..self.board_width {
-            for y in 0..self.board_height {
+        let (width, height) = self.board_size();
+        for x in 0..width {
+            for y in 0..height {
                 if (x + y) % 2 == 0 {
                     let rect = self.square_from_coords(x, y, to_screen);
                     painter.rect_filled(rect, 0., Color32::GRAY);
@@ -223,161 +434,942 @@ impl AmazonsGame {
         }
         match self.state {
             ClickableState::GameInProgress | ClickableState::GameOver(_) => {
-                for x in 0..self.board_width {
-                    for y in 0..self.board_height {
-                        let rect = self.square_from_coords(x, y, to_screen);
-                        let mut sq = Square::new(x, y);
-                        unsafe {
-                            match boardstate_squareState(&mut self.boardstate, &mut sq) {
-                                SquareState_WHITE => {
-                                    AmazonsGame::draw_sprite(rect, self.white_sprite, painter)
-                                }
-                                SquareState_BLACK => {
-                                    AmazonsGame::draw_sprite(rect, self.black_sprite, painter)
-                                }
-                                SquareState_ARROW => {
-                                    AmazonsGame::draw_sprite(rect, self.arrow_sprite, painter)
-                                }
-                                _ => (),
-                            }
-                        }
-                    }
+                self.draw_position(self.displayed_board(), painter, to_screen);
+                if self.show_territory && self.state == ClickableState::GameInProgress {
+                    self.draw_territory(painter, to_screen);
+                }
+                if self.viewing.is_some() {
+                    return;
                 }
                 if self.clicked_square == 2 {
-                    let (x, y) = self.dst_square.destructure();
+                    let Coord { x, y } = self.dst_square;
                     let rect = self.square_from_coords(x, y, to_screen);
                     painter.rect_filled(rect, 0., Color32::RED);
                 }
                 if self.clicked_square >= 1 {
-                    let (x, y) = self.src_square.destructure();
+                    let Coord { x, y } = self.src_square;
                     let rect = self.square_from_coords(x, y, to_screen);
                     painter.rect_filled(rect, 0., Color32::from_rgba_unmultiplied(0, 255, 0, 128))
                 }
+                self.draw_targets(painter, to_screen);
+                if let Some(hint) = self.hint {
+                    let squares = [
+                        (hint.from, Color32::from_rgba_unmultiplied(0, 255, 0, 96)),
+                        (hint.to, Color32::from_rgba_unmultiplied(255, 0, 0, 96)),
+                        (hint.arrow, Color32::from_rgba_unmultiplied(255, 255, 0, 96)),
+                    ];
+                    for (Coord { x, y }, colour) in squares {
+                        let rect = self.square_from_coords(x, y, to_screen);
+                        painter.rect_filled(rect, 0., colour);
+                    }
+                }
                 if let ClickableState::GameOver(_) = self.state {
                     if self.highlight_regions {
-                        for x in 0..self.board_width {
-                            for y in 0..self.board_height {
+                        for x in 0..width {
+                            for y in 0..height {
                                 let rect = self.square_from_coords(x, y, to_screen);
-                                let mut sq = Square::new(x, y);
-                                unsafe {
-                                    match boardstate_squareController(&mut self.boardstate, &mut sq)
-                                    {
-                                        SquareState_WHITE => painter.rect_filled(
-                                            rect,
-                                            0.,
-                                            Color32::from_rgba_unmultiplied(255, 0, 0, 128),
-                                        ),
-                                        SquareState_BLACK => painter.rect_filled(
-                                            rect,
-                                            0.,
-                                            Color32::from_rgba_unmultiplied(0, 0, 255, 128),
-                                        ),
-                                        _ => (),
+                                let colour = match self.board.controller(Coord::new(x, y)) {
+                                    Ok(Some(Player::White)) => {
+                                        Color32::from_rgba_unmultiplied(255, 0, 0, 128)
                                     }
-                                }
+                                    Ok(Some(Player::Black)) => {
+                                        Color32::from_rgba_unmultiplied(0, 0, 255, 128)
+                                    }
+                                    _ => continue,
+                                };
+                                painter.rect_filled(rect, 0., colour);
                             }
                         }
                     }
                 }
             }
             ClickableState::Idle if self.is_empty_config() => {
-                for (x, y) in vec![(3u32, 0u32), (0, 3), (0, 6), (3, 9)] {
+                for (x, y) in [(3u32, 0u32), (0, 3), (0, 6), (3, 9)] {
                     let rect = self.square_from_coords(x, y, to_screen);
-                    AmazonsGame::draw_sprite(rect, self.white_sprite, painter);
+                    Self::draw_sprite(rect, self.white_sprite, painter);
                 }
-                for (x, y) in vec![(6u32, 0u32), (9, 3), (9, 6), (6, 9)] {
+                for (x, y) in [(6u32, 0u32), (9, 3), (9, 6), (6, 9)] {
                     let rect = self.square_from_coords(x, y, to_screen);
-                    AmazonsGame::draw_sprite(rect, self.black_sprite, painter);
+                    Self::draw_sprite(rect, self.black_sprite, painter);
                 }
             }
             _ => {
                 for (x, y) in &self.white_starting {
                     let rect = self.square_from_coords(*x, *y, to_screen);
-                    AmazonsGame::draw_sprite(rect, self.white_sprite, painter);
+                    Self::draw_sprite(rect, self.white_sprite, painter);
                 }
                 for (x, y) in &self.black_starting {
                     let rect = self.square_from_coords(*x, *y, to_screen);
-                    AmazonsGame::draw_sprite(rect, self.black_sprite, painter);
+                    Self::draw_sprite(rect, self.black_sprite, painter);
                 }
             }
         }
     }
 
-    fn set_src(&mut self, x: u32, y: u32) -> bool {
-        self.src_square = Square::new(x, y);
-        unsafe {
-            boardstate_squareState(&mut self.boardstate, &mut self.src_square)
-                == self.boardstate.currentPlayer
+    fn draw_position(&self, board: &E, painter: &Painter, to_screen: RectTransform) {
+        for x in 0..board.width() {
+            for y in 0..board.height() {
+                let rect = self.square_from_coords(x, y, to_screen);
+                let sprite = match board.cell(Coord::new(x, y)) {
+                    Ok(Cell::Amazon(Player::White)) => self.white_sprite,
+                    Ok(Cell::Amazon(Player::Black)) => self.black_sprite,
+                    Ok(Cell::Arrow) => self.arrow_sprite,
+                    _ => continue,
+                };
+                Self::draw_sprite(rect, sprite, painter);
+            }
+        }
+    }
+
+    /// Marks the squares the selected amazon can move to or, once the
+    /// destination is chosen, shoot at
+    fn draw_targets(&self, painter: &Painter, to_screen: RectTransform) {
+        let (targets, colour) = match self.clicked_square {
+            1 => (
+                destinations(&self.board, self.src_square),
+                Color32::from_rgba_unmultiplied(0, 255, 0, 160),
+            ),
+            2 => (
+                arrow_targets(&self.board, self.src_square, self.dst_square),
+                Color32::from_rgba_unmultiplied(255, 0, 0, 160),
+            ),
+            _ => return,
+        };
+        for Coord { x, y } in targets {
+            let rect = self.square_from_coords(x, y, to_screen);
+            painter.circle_filled(rect.center(), rect.width() * 0.15, colour);
+        }
+    }
+
+    fn draw_territory(&self, painter: &Painter, to_screen: RectTransform) {
+        let position = Position::from_engine(self.displayed_board());
+        let territory = Territory::new(&position, false);
+        for (i, owner) in territory.owners.iter().enumerate() {
+            let colour = match owner {
+                Some(Player::White) => Color32::from_rgba_unmultiplied(255, 0, 0, 64),
+                Some(Player::Black) => Color32::from_rgba_unmultiplied(0, 0, 255, 64),
+                None => continue,
+            };
+            let (x, y) = (i as u32 % position.width, i as u32 / position.width);
+            painter.rect_filled(self.square_from_coords(x, y, to_screen), 0., colour);
         }
     }
 
-    fn set_dst(&mut self, x: u32, y: u32) -> bool {
-        let mut dst = Square::new(x, y);
-        unsafe {
-            if isValidMove(&mut self.boardstate, &mut self.src_square, &mut dst) == 1 {
-                self.dst_square = dst;
-                return true;
+    fn territory_estimate(&self, ui: &mut Ui) {
+        let board = self.displayed_board();
+        let territory = Territory::new(&Position::from_engine(board), false);
+        ui.label(format!(
+            "Estimated squares: {} - {}",
+            territory.white, territory.black
+        ));
+        // Positive scores favour the bows
+        let score = match board.current_player() {
+            Player::White => evaluate(board),
+            Player::Black => -evaluate(board),
+        };
+        ui.label(format!("Evaluation: {:+}", score));
+    }
+
+    /// The position shown on the board: an earlier one when browsing the
+    /// move list, otherwise the live game
+    fn displayed_board(&self) -> &E {
+        self.viewing
+            .and_then(|turns| self.history.position(turns))
+            .unwrap_or(&self.board)
+    }
+
+    fn view(&mut self, turns: usize) {
+        self.viewing = if turns < self.history.cursor() {
+            Some(turns)
+        } else {
+            None
+        };
+    }
+
+    fn set_src(&mut self, x: u32, y: u32) -> Result<(), BoardError> {
+        self.src_square = Coord::new(x, y);
+        check_source(&self.board, self.src_square)
+    }
+
+    fn set_dst(&mut self, x: u32, y: u32) -> Result<(), BoardError> {
+        let dst = Coord::new(x, y);
+        check_line(&self.board, self.src_square, dst, None)?;
+        self.dst_square = dst;
+        Ok(())
+    }
+
+    fn move_amazon(&mut self, x: u32, y: u32) -> Result<(), BoardError> {
+        let arrow = Coord::new(x, y);
+        // The amazon only leaves its square once the whole turn is played
+        check_line(&self.board, self.dst_square, arrow, Some(self.src_square))?;
+        self.shot_square = arrow;
+        let mv = Move::new(self.src_square, self.dst_square, self.shot_square);
+        self.play_turn(mv)
+    }
+
+    // Plays a full turn for the player to move and checks whether it ends the game
+    fn play_turn(&mut self, mv: Move) -> Result<(), BoardError> {
+        let mover = self.board.current_player();
+        mv.play(&mut self.board)?;
+        self.src_square = mv.from;
+        self.dst_square = mv.to;
+        self.shot_square = mv.arrow;
+        self.history.push(mv, self.board.clone());
+        self.clear_hint();
+        let sent = match &mut self.session {
+            Some(session) if session.local_player() == Some(mover) => session.send_move(mv),
+            _ => Ok(()),
+        };
+        if let Err(e) = sent {
+            self.network_failed(&e.to_string());
+        }
+        if let Some(winner) = self.game_winner() {
+            self.state = ClickableState::GameOver(winner);
+        }
+        Ok(())
+    }
+
+    fn rule_message(&mut self, ui: &mut Ui) {
+        match &self.rule_message {
+            Some((message, at)) if at.elapsed() < RULE_MESSAGE_TIME => {
+                ui.colored_label(Color32::RED, message);
             }
+            _ => self.rule_message = None,
         }
-        false
     }
 
-    fn move_amazon(&mut self, x: u32, y: u32) -> bool {
-        let mut shot = Square::new(x, y);
-        unsafe {
-            if boardstate_squareState(&mut self.boardstate, &mut self.src_square)
-                == self.boardstate.currentPlayer
-                && amazons_move(
-                    &mut self.boardstate,
-                    &mut self.src_square,
-                    &mut self.dst_square,
-                ) == 1
-            {
-                if amazons_shoot(&mut self.boardstate, &mut self.dst_square, &mut shot) == 1 {
-                    swapPlayer(&mut self.boardstate.currentPlayer);
-                    return true;
-                } else {
-                    amazons_move(
-                        &mut self.boardstate,
-                        &mut self.dst_square,
-                        &mut self.src_square,
-                    );
+    fn player_kind(&self, player: Player) -> PlayerKind {
+        if let Some(session) = &self.session {
+            return if session.local_player() == Some(player) {
+                PlayerKind::Human
+            } else {
+                PlayerKind::Remote
+            };
+        }
+        match player {
+            Player::White => self.white_player,
+            Player::Black => self.black_player,
+        }
+    }
+
+    fn computer_to_move(&self) -> bool {
+        self.state == ClickableState::GameInProgress
+            && self.player_kind(self.board.current_player()) != PlayerKind::Human
+    }
+
+    fn cancel_search(&mut self) {
+        // Dropping the handle stops the worker thread
+        self.search = None;
+        let engines = [&mut self.white_engine, &mut self.black_engine];
+        for engine in engines.into_iter().flatten() {
+            engine.abandon();
+        }
+    }
+
+    fn stop_engines(&mut self) {
+        // Dropping an engine kills its process
+        self.white_engine = None;
+        self.black_engine = None;
+        self.engine_message = None;
+    }
+
+    fn engine_thinking(&self) -> bool {
+        let thinking = |engine: &Option<ExternalEngine>| {
+            engine.as_ref().is_some_and(ExternalEngine::is_thinking)
+        };
+        thinking(&self.white_engine) || thinking(&self.black_engine)
+    }
+
+    // Hands the side of a failed engine over to a human
+    fn engine_failed(&mut self, player: Player, reason: &str) {
+        let name = match player {
+            Player::White => {
+                self.white_engine = None;
+                self.white_player = PlayerKind::Human;
+                &self.white_name
+            }
+            Player::Black => {
+                self.black_engine = None;
+                self.black_player = PlayerKind::Human;
+                &self.black_name
+            }
+        };
+        self.engine_message = Some(format!(
+            "{}'s engine stopped: {}. The rest of the game is played by hand.",
+            name, reason
+        ));
+    }
+
+    fn clear_hint(&mut self) {
+        self.hint_search = None;
+        self.hint = None;
+    }
+
+    fn host_game(&mut self) {
+        let board = match self.starting_board() {
+            Ok(board) => board,
+            Err(e) => {
+                self.state = ClickableState::InvalidConfig(e);
+                return;
+            }
+        };
+        let listener = match Listener::bind(self.net_port) {
+            Ok(listener) => listener,
+            Err(e) => {
+                self.net_message = Some(format!("Could not host a game: {}", e));
+                return;
+            }
+        };
+        let port = listener.port().unwrap_or(self.net_port);
+        let announcement = Announcement::new(&self.white_name, port, &board);
+        self.net_message = None;
+        match Announcer::new(&announcement, DISCOVERY_PORT) {
+            Ok(announcer) => self.announcer = Some(announcer),
+            Err(e) => {
+                self.net_message = Some(format!(
+                    "The game is not announced on the local network: {}",
+                    e
+                ))
+            }
+        }
+        self.listener = Some(listener);
+        self.state = ClickableState::Connecting;
+    }
+
+    fn open_join_dialog(&mut self) {
+        self.net_message = None;
+        match Browser::bind(DISCOVERY_PORT) {
+            Ok(browser) => self.browser = Some(browser),
+            Err(e) => {
+                self.net_message = Some(format!("Cannot look for games on the network: {}", e))
+            }
+        }
+        self.state = ClickableState::Joining;
+    }
+
+    fn join_game(&mut self) {
+        self.browser = None;
+        self.dialer = Some(Dialer::connect(self.net_address.trim()));
+        self.net_message = None;
+        self.state = ClickableState::Connecting;
+    }
+
+    fn join_dialog(&mut self, ui: &mut Ui) {
+        ui.heading("Join Game");
+        let mut chosen = None;
+        match self.browser.as_mut().map(Browser::poll) {
+            Some([]) => {
+                ui.label("Looking for games on the local network…");
+            }
+            Some(games) => {
+                for game in games {
+                    let ann = &game.announcement;
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{}: {}x{}, {} vs {} amazons",
+                            ann.name, ann.width, ann.height, ann.white, ann.black
+                        ));
+                        if ui.button("Join").clicked() {
+                            chosen = Some(game.address);
+                        }
+                    });
+                }
+            }
+            None => (),
+        }
+        if let Some(address) = chosen {
+            self.net_address = address.to_string();
+            self.join_game();
+            return;
+        }
+        ui.horizontal(|ui| {
+            ui.label("Address");
+            ui.text_edit_singleline(&mut self.net_address)
+                .on_hover_text("Host name or address, optionally followed by :port");
+        });
+        if ui.button("Connect").clicked() {
+            self.join_game();
+        }
+        if let Some(message) = &self.net_message {
+            ui.colored_label(Color32::RED, message);
+        }
+        if ui.button("Back").clicked() {
+            self.browser = None;
+            self.state = ClickableState::Idle;
+        }
+    }
+
+    /// Accepts or completes the connection of a network game and handles
+    /// what the other side sent
+    fn update_network(&mut self) {
+        if let Some(announcer) = &mut self.announcer {
+            announcer.tick();
+        }
+        let accepted = self.listener.as_ref().and_then(Listener::accept);
+        if let Some(connection) = accepted {
+            self.listener = None;
+            self.announcer = None;
+            // The host plays player 1
+            let session = connection
+                .map_err(|e| e.to_string())
+                .and_then(|connection| {
+                    let board = self.starting_board()?;
+                    Session::host(connection, &self.white_name, &board, Player::Black)
+                        .map_err(|e| e.to_string())
+                });
+            match session {
+                Ok(session) => self.session = Some(session),
+                Err(e) => self.network_failed(&e),
+            }
+        }
+        let connected = self.dialer.as_ref().and_then(Dialer::try_connection);
+        if let Some(connection) = connected {
+            self.dialer = None;
+            // On a server the side is not known yet, so we go by player 1's name
+            let lobby = self.state == ClickableState::Lobby;
+            let name = if lobby {
+                &self.white_name
+            } else {
+                &self.black_name
+            };
+            let session = connection.and_then(|c| Session::join(c, name));
+            match session {
+                Ok(session) => self.session = Some(session),
+                Err(e) => self.network_failed(&e.to_string()),
+            }
+            if lobby {
+                self.lobby_request(&Message::List);
+            }
+        }
+        match self.session.as_mut().and_then(Session::poll) {
+            Some(Ok(Event::Started { board, local, peer })) => {
+                let own = self.session.as_ref().map(|s| s.name().to_string());
+                match (local, own) {
+                    (Some(Player::White), Some(own)) => {
+                        self.white_name = own;
+                        self.black_name = peer;
+                    }
+                    (Some(Player::Black), Some(own)) => {
+                        self.white_name = peer;
+                        self.black_name = own;
+                    }
+                    _ => (),
+                }
+                self.start_game(board);
+            }
+            Some(Ok(Event::Lobby(games))) => self.lobby_games = games,
+            Some(Ok(Event::Created(id))) => self.created_game = Some(id),
+            Some(Ok(Event::Denied(reason))) => {
+                self.net_message = Some(capitalize(&reason));
+            }
+            Some(Ok(Event::Move(mv))) => {
+                // The session has already checked the turn against its own board
+                if let Err(e) = self.play_turn(mv) {
+                    self.network_failed(&format!("cannot play {}: {}", mv, e));
                 }
             }
+            Some(Err(e)) => self.network_failed(&e.to_string()),
+            None => (),
         }
-        false
     }
 
-    fn game_winner(&mut self) -> SquareState {
-        let (mut ws, mut bs) = (0, 0);
-        let (winner, moves_left) = unsafe {
-            (
-                boardstate_winner(&mut self.boardstate, &mut ws, &mut bs),
-                playerHasValidMove(&mut self.boardstate, self.boardstate.currentPlayer) == 1,
-            )
+    fn open_lobby(&mut self) {
+        self.lobby_games.clear();
+        self.created_game = None;
+        self.net_message = None;
+        self.state = ClickableState::Lobby;
+    }
+
+    fn lobby_request(&mut self, message: &Message) {
+        let sent = match &mut self.session {
+            Some(session) => session.request(message),
+            None => return,
         };
-        if winner != SquareState_EMPTY {
-            self.highlight_regions = moves_left;
-            self.white_squares = ws as u32;
-            self.black_squares = bs as u32;
-            if ws == bs {
-                return if winner == SquareState_BLACK {
-                    SquareState_WHITE
-                } else {
-                    SquareState_BLACK
+        if let Err(e) = sent {
+            self.network_failed(&e.to_string());
+        }
+    }
+
+    fn create_server_game(&mut self) {
+        match self.starting_board() {
+            Ok(board) => {
+                self.net_message = None;
+                self.lobby_request(&Message::Create(Position::from_engine(&board)));
+            }
+            Err(e) => self.net_message = Some(e),
+        }
+    }
+
+    fn lobby(&mut self, ui: &mut Ui) {
+        ui.heading("Server Lobby");
+        if self.session.is_none() && self.dialer.is_none() {
+            ui.horizontal(|ui| {
+                ui.label("Server");
+                ui.text_edit_singleline(&mut self.server_address)
+                    .on_hover_text("Host name or address, optionally followed by :port");
+            });
+            if ui.button("Connect").clicked() {
+                self.net_message = None;
+                self.dialer = Some(Dialer::connect(self.server_address.trim()));
+            }
+        } else if self.session.is_none() {
+            ui.label(format!("Connecting to {}", self.server_address.trim()));
+        } else if let Some(id) = self.created_game {
+            ui.label(format!("Waiting for an opponent to join game {}", id));
+        } else {
+            ui.horizontal(|ui| {
+                if ui.button("Create game").clicked() {
+                    self.create_server_game();
+                }
+                if ui.button("Refresh").clicked() {
+                    self.lobby_request(&Message::List);
+                }
+            });
+            if self.lobby_games.is_empty() {
+                ui.label("No games yet");
+            }
+            let mut request = None;
+            for game in &self.lobby_games {
+                let status = match game.status {
+                    GameStatus::Open => "open",
+                    GameStatus::Playing => "playing",
+                    GameStatus::Over => "over",
                 };
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "{}. {}: {}x{}, {}",
+                        game.id, game.title, game.start.width, game.start.height, status
+                    ));
+                    let open = game.status == GameStatus::Open;
+                    if ui.add_enabled(open, Button::new("Join")).clicked() {
+                        request = Some(Message::Join(game.id));
+                    }
+                    if ui.button("Watch").clicked() {
+                        request = Some(Message::Watch(game.id));
+                    }
+                });
+            }
+            if let Some(request) = request {
+                self.lobby_request(&request);
+            }
+        }
+        if let Some(message) = &self.net_message {
+            ui.colored_label(Color32::RED, message);
+        }
+        if ui.button("Back").clicked() {
+            self.leave_network();
+            self.state = ClickableState::Idle;
+        }
+    }
+
+    fn leave_network(&mut self) {
+        // Dropping the connection tells the other side
+        self.listener = None;
+        self.dialer = None;
+        self.announcer = None;
+        self.browser = None;
+        self.session = None;
+    }
+
+    // Ends the network game; a game in progress continues on this screen
+    fn network_failed(&mut self, reason: &str) {
+        self.leave_network();
+        if self.state == ClickableState::Lobby {
+            self.created_game = None;
+            self.net_message = Some(format!("Disconnected: {}", reason));
+        } else if let ClickableState::Connecting | ClickableState::Joining = self.state {
+            self.net_message = Some(format!("Network game ended: {}", reason));
+            self.state = ClickableState::Idle;
+        } else {
+            self.net_message = Some(format!(
+                "Network game ended: {}. The rest of the game is played on this screen.",
+                reason
+            ));
+        }
+    }
+
+    fn network_settings(&mut self, ui: &mut Ui) {
+        let sep = Separator::default().spacing(12.).horizontal();
+        ui.add(sep);
+        ui.horizontal(|ui| {
+            ui.label("Port");
+            ui.add(DragValue::new(&mut self.net_port));
+            if ui.button("Host game").clicked() {
+                self.host_game();
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Join game").clicked() {
+                self.open_join_dialog();
+            }
+            if ui.button("Connect to server").clicked() {
+                self.open_lobby();
             }
-            return winner;
+        });
+        if let Some(message) = &self.net_message {
+            ui.colored_label(Color32::RED, message);
         }
-        if moves_left {
-            return SquareState_EMPTY;
+    }
+
+    fn mcts_settings(&mut self, ui: &mut Ui) {
+        let mut by_playouts = self.mcts_playouts.is_some();
+        ui.checkbox(&mut by_playouts, "Limit MCTS by playouts instead of time");
+        if by_playouts {
+            let playouts = self.mcts_playouts.get_or_insert(1000);
+            let slider = Slider::new(playouts, 10..=100_000)
+                .logarithmic(true)
+                .text("MCTS playouts");
+            ui.add(slider);
+        } else {
+            self.mcts_playouts = None;
+        }
+        let slider = Slider::new(&mut self.mcts_exploration, 0.0..=3.0).text("MCTS exploration");
+        ui.add(slider);
+    }
+
+    fn undo_turn(&mut self) {
+        // Both sides of a network game must keep the same history
+        if self.session.is_some() {
+            return;
+        }
+        if let Some(board) = self.history.undo() {
+            self.board = board.clone();
+            self.resume_from_history();
+        }
+        // Against the computer, go back to the last position a human played from
+        let human = |kind| kind == PlayerKind::Human;
+        let someone_human = human(self.white_player) || human(self.black_player);
+        while someone_human && self.computer_to_move() && self.history.can_undo() {
+            if let Some(board) = self.history.undo() {
+                self.board = board.clone();
+                self.resume_from_history();
+            }
         }
-        if self.boardstate.currentPlayer == SquareState_BLACK {
-            SquareState_WHITE
+    }
+
+    fn redo_turn(&mut self) {
+        if self.session.is_some() {
+            return;
+        }
+        if let Some(board) = self.history.redo() {
+            self.board = board.clone();
+            self.resume_from_history();
+        }
+    }
+
+    fn resume_from_history(&mut self) {
+        self.cancel_search();
+        self.clear_hint();
+        self.viewing = None;
+        self.clicked_square = 0;
+        self.highlight_regions = false;
+        self.state = match self.game_winner() {
+            Some(winner) => ClickableState::GameOver(winner),
+            None => ClickableState::GameInProgress,
+        };
+    }
+
+    fn game_winner(&mut self) -> Option<Player> {
+        let outcome = self.board.winner().ok()?;
+        if let Some((ws, bs)) = outcome.territory {
+            self.white_squares = ws;
+            self.black_squares = bs;
+        }
+        self.highlight_regions = outcome.territory.is_some();
+        Some(outcome.winner)
+    }
+
+    fn save_game(&mut self) {
+        let start = match self.history.position(0) {
+            Some(start) => start,
+            None => return,
+        };
+        let moves = &self.history.moves()[..self.history.cursor()];
+        let record = match GameRecord::new(start, moves, &self.white_name, &self.black_name) {
+            Ok(record) => record,
+            Err(e) => {
+                self.file_message = Some(format!("Could not save game: {}", e));
+                return;
+            }
+        };
+        let text = if self.is_sgf_file() {
+            let mut sgf = self.sgf.take().unwrap_or_default();
+            sgf.update(&record);
+            let text = sgf.to_string();
+            self.sgf = Some(sgf);
+            text
         } else {
-            SquareState_BLACK
+            record.to_string()
+        };
+        let saved = std::fs::write(&self.game_file, text).map_err(|e| e.to_string());
+        self.file_message = Some(match saved {
+            Ok(()) => format!("Saved game to {}", self.game_file),
+            Err(e) => format!("Could not save game: {}", e),
+        });
+    }
+
+    fn load_game(&mut self) {
+        let sgf_file = self.is_sgf_file();
+        let loaded = std::fs::read_to_string(&self.game_file)
+            .map_err(|e| e.to_string())
+            .and_then(|text| {
+                if !sgf_file {
+                    let record = text.parse::<GameRecord>().map_err(|e| e.to_string())?;
+                    return Ok((record, None));
+                }
+                let sgf = text.parse::<SgfGame>().map_err(|e| e.to_string())?;
+                let record = sgf.to_record::<E>().map_err(|e| e.to_string())?;
+                Ok((record, Some(sgf)))
+            })
+            .and_then(|(record, sgf)| {
+                let history = record.replay::<E>().map_err(|e| e.to_string())?;
+                Ok((record, sgf, history))
+            });
+        let (record, sgf, history) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                self.file_message = Some(format!("Could not load game: {}", e));
+                return;
+            }
+        };
+        self.white_name = record.white_name;
+        self.black_name = record.black_name;
+        self.sgf = sgf;
+        self.start_from_history(history);
+        self.file_message = Some(format!("Loaded game from {}", self.game_file));
+    }
+
+    fn is_sgf_file(&self) -> bool {
+        self.game_file.to_ascii_lowercase().ends_with(".sgf")
+    }
+
+    fn copy_position(&mut self, ui: &mut Ui) {
+        self.position_text = Position::from_engine(self.displayed_board()).to_string();
+        ui.output().copied_text = self.position_text.clone();
+        self.file_message = Some("Copied position to clipboard".to_string());
+    }
+
+    fn set_up_position(&mut self) {
+        let board = self
+            .position_text
+            .parse::<Position>()
+            .map_err(|e| e.to_string())
+            .and_then(|position| position.to_engine::<E>().map_err(|e| e.to_string()));
+        match board {
+            Ok(board) => {
+                self.sgf = None;
+                self.start_from_history(History::new(board));
+                self.file_message = Some("Set up position".to_string());
+            }
+            Err(e) => self.file_message = Some(format!("Could not set up position: {}", e)),
+        }
+    }
+
+    // Continues from the current position of the given history, updating the
+    // settings to match its starting position
+    fn start_from_history(&mut self, history: History<E>) {
+        if let Some(start) = history.position(0) {
+            let position = Position::from_engine(start);
+            let to_pairs = |coords: Vec<Coord>| coords.iter().map(|c| (c.x, c.y)).collect();
+            let white = position.squares(Cell::Amazon(Player::White));
+            let black = position.squares(Cell::Amazon(Player::Black));
+            self.board_width = position.width;
+            self.board_height = position.height;
+            self.white_amazons = white.len() as u32;
+            self.black_amazons = black.len() as u32;
+            self.white_starting = to_pairs(white);
+            self.black_starting = to_pairs(black);
+        }
+        if let Some(board) = history.current() {
+            self.board = board.clone();
+        }
+        self.history = history;
+        self.stop_engines();
+        self.leave_network();
+        self.resume_from_history();
+    }
+
+    #[cfg(feature = "persistence")]
+    fn saved_game(&self) -> Option<SavedGame> {
+        if let ClickableState::Idle
+        | ClickableState::PickingWhite
+        | ClickableState::PickingBlack
+        | ClickableState::Joining
+        | ClickableState::Connecting
+        | ClickableState::Lobby = self.state
+        {
+            return None;
+        }
+        let start = self.history.position(0)?;
+        let record = GameRecord::new(
+            start,
+            self.history.moves(),
+            &self.white_name,
+            &self.black_name,
+        )
+        .ok()?;
+        Some(SavedGame {
+            record: record.to_string(),
+            turns: self.history.cursor(),
+            clicked_square: self.clicked_square,
+            src_square: (self.src_square.x, self.src_square.y),
+            dst_square: (self.dst_square.x, self.dst_square.y),
+            winner: match self.state {
+                ClickableState::GameOver(winner) => Some(winner),
+                _ => None,
+            },
+        })
+    }
+
+    #[cfg(feature = "persistence")]
+    fn restore_game(&mut self, saved: SavedGame) -> Result<(), String> {
+        let record = saved
+            .record
+            .parse::<GameRecord>()
+            .map_err(|e| e.to_string())?;
+        let mut history = record.replay::<E>().map_err(|e| e.to_string())?;
+        while history.cursor() > saved.turns && history.undo().is_some() {}
+        if let Some(board) = history.current() {
+            self.board = board.clone();
+        }
+        let replayed = history.cursor() == saved.turns;
+        self.history = history;
+        self.resume_from_history();
+        // A resignation or forfeit ends the game before the rules do
+        if let (ClickableState::GameInProgress, Some(winner), true) =
+            (&self.state, saved.winner, replayed)
+        {
+            self.state = ClickableState::GameOver(winner);
         }
+        // Only restore the selection if it is still valid in the restored position
+        if self.state == ClickableState::GameInProgress && saved.clicked_square >= 1 {
+            let (x, y) = saved.src_square;
+            if self.set_src(x, y).is_ok() {
+                self.clicked_square = 1;
+                let (x, y) = saved.dst_square;
+                if saved.clicked_square == 2 && self.set_dst(x, y).is_ok() {
+                    self.clicked_square = 2;
+                }
+            }
+        }
+        Ok(())
     }
+
+    fn file_controls(&mut self, ui: &mut Ui) {
+        let sep = Separator::default().spacing(12.).horizontal();
+        ui.add(sep);
+        ui.horizontal(|ui| {
+            ui.label("Game file");
+            ui.text_edit_singleline(&mut self.game_file)
+                .on_hover_text("Files ending in .sgf are read and written as SGF");
+        });
+        ui.horizontal(|ui| {
+            let in_game = self.state != ClickableState::Idle;
+            if ui.add_enabled(in_game, Button::new("Save game")).clicked() {
+                self.save_game();
+            }
+            if ui.button("Load game").clicked() {
+                self.load_game();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Position");
+            ui.text_edit_singleline(&mut self.position_text);
+        });
+        ui.horizontal(|ui| {
+            let in_game = self.state != ClickableState::Idle;
+            if ui
+                .add_enabled(in_game, Button::new("Copy position"))
+                .clicked()
+            {
+                self.copy_position(ui);
+            }
+            if ui.button("Set up position").clicked() {
+                self.set_up_position();
+            }
+        });
+        if let Some(message) = &self.file_message {
+            ui.label(message);
+        }
+    }
+
+    fn history_buttons(&mut self, ui: &mut Ui) {
+        let local = self.session.is_none();
+        let undo = Button::new("Undo last turn");
+        if ui
+            .add_enabled(local && self.history.can_undo(), undo)
+            .on_hover_text("Ctrl+Z")
+            .clicked()
+        {
+            self.undo_turn();
+        }
+        let redo = Button::new("Redo turn");
+        if ui
+            .add_enabled(local && self.history.can_redo(), redo)
+            .on_hover_text("Ctrl+Y")
+            .clicked()
+        {
+            self.redo_turn();
+        }
+    }
+
+    fn move_list(&mut self, ui: &mut Ui) {
+        let sep = Separator::default().spacing(12.).horizontal();
+        ui.add(sep);
+        ui.heading("Moves");
+
+        let live = self.history.cursor();
+        let shown = self.viewing.unwrap_or(live);
+        ui.horizontal(|ui| {
+            if ui.add_enabled(shown > 0, Button::new("|<")).clicked() {
+                self.view(0);
+            }
+            if ui.add_enabled(shown > 0, Button::new("<")).clicked() {
+                self.view(shown - 1);
+            }
+            if ui.add_enabled(shown < live, Button::new(">")).clicked() {
+                self.view(shown + 1);
+            }
+            if ui.add_enabled(shown < live, Button::new(">|")).clicked() {
+                self.view(live);
+            }
+        });
+        if self.viewing.is_some() && ui.button("Resume game").clicked() {
+            self.view(live);
+        }
+        let comment = self
+            .sgf
+            .as_ref()
+            .and_then(|sgf| sgf.comment(&self.history.moves()[..shown]));
+        if let Some(comment) = comment {
+            ui.label(comment);
+        }
+
+        let mut clicked = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (i, mv) in self.history.moves()[..live].iter().enumerate() {
+                let mover = self.history.position(i).map(|board| board.current_player());
+                let side = match mover {
+                    Some(Player::Black) => "Spears",
+                    _ => "Bows",
+                };
+                let label = format!("{}. {} {}", i + 1, side, mv);
+                if ui.selectable_label(shown == i + 1, label).clicked() {
+                    clicked = Some(i + 1);
+                }
+            }
+        });
+        if let Some(turns) = clicked {
+            self.view(turns);
+        }
+    }
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .map_or_else(String::new, |c| c.to_uppercase().chain(chars).collect())
 }
 
 fn number_setting(ui: &mut Ui, num: &mut u32, min: u32, max: u32, lbl: &str) {
@@ -385,31 +1377,234 @@ fn number_setting(ui: &mut Ui, num: &mut u32, min: u32, max: u32, lbl: &str) {
     ui.add(slider);
 }
 
-impl epi::App for AmazonsGame {
+fn player_setting(ui: &mut Ui, kind: &mut PlayerKind, engine_path: &mut String, lbl: &str) {
+    egui::ComboBox::from_label(lbl)
+        .selected_text(kind.label())
+        .show_ui(ui, |ui| {
+            for option in PlayerKind::ALL {
+                ui.selectable_value(kind, option, option.label());
+            }
+        });
+    if *kind == PlayerKind::External {
+        ui.horizontal(|ui| {
+            ui.label("Engine path");
+            ui.text_edit_singleline(engine_path);
+        });
+    }
+}
+
+impl<E: AmazonsEngine + Clone + Default + Send + 'static> AmazonsGame<E> {
+    /// Starts the computer's search when it is to move and plays its turn
+    /// once the search has finished
+    fn update_search(&mut self) {
+        if !self.computer_to_move() {
+            self.cancel_search();
+            return;
+        }
+        match self.player_kind(self.board.current_player()) {
+            PlayerKind::External => return self.update_engine(),
+            // Turns of the other side of a network game arrive in update_network
+            PlayerKind::Remote => return,
+            _ => (),
+        }
+        let handle = match &self.search {
+            Some(handle) => handle,
+            None => {
+                self.search = Some(self.start_search());
+                return;
+            }
+        };
+        let mv = match handle.try_result() {
+            Some(mv) => mv,
+            None => return,
+        };
+        self.search = None;
+        // Play the turn the same way as a human clicking the three squares
+        if let Some(Move { from, to, arrow }) = mv {
+            self.clicked_square = 0;
+            let played = self
+                .set_src(from.x, from.y)
+                .and_then(|_| self.set_dst(to.x, to.y))
+                .and_then(|_| self.move_amazon(arrow.x, arrow.y));
+            if let Err(e) = played {
+                self.rule_message =
+                    Some((format!("Computer turn rejected: {}", e), Instant::now()));
+            }
+        }
+    }
+
+    /// Asks the external engine playing the side to move for a turn and
+    /// plays it once it has answered
+    fn update_engine(&mut self) {
+        let player = self.board.current_player();
+        let mv = match self.poll_engine(player) {
+            Ok(Some(mv)) => mv,
+            Ok(None) => return,
+            Err(e) => {
+                self.engine_failed(player, &e.to_string());
+                return;
+            }
+        };
+        let mv = match mv {
+            Some(mv) => mv,
+            None => {
+                let name = match player {
+                    Player::White => &self.white_name,
+                    Player::Black => &self.black_name,
+                };
+                self.engine_message = Some(format!("{}'s engine resigned", name));
+                self.state = ClickableState::GameOver(player.opponent());
+                return;
+            }
+        };
+        self.clicked_square = 0;
+        let played = self
+            .set_src(mv.from.x, mv.from.y)
+            .and_then(|_| self.set_dst(mv.to.x, mv.to.y))
+            .and_then(|_| self.move_amazon(mv.arrow.x, mv.arrow.y));
+        if let Err(e) = played {
+            self.engine_failed(player, &format!("it played {}, but {}", mv, e));
+        }
+    }
+
+    // Starts the engine if needed and sends it the game once per turn
+    fn poll_engine(&mut self, player: Player) -> Result<Option<Option<Move>>, ClientError> {
+        let (slot, path) = match player {
+            Player::White => (&mut self.white_engine, &self.white_engine_path),
+            Player::Black => (&mut self.black_engine, &self.black_engine_path),
+        };
+        let engine = match slot {
+            Some(engine) => engine,
+            None => slot.insert(ExternalEngine::spawn(path.trim())?),
+        };
+        if !engine.is_thinking() {
+            if let Some(start) = self.history.position(0) {
+                let moves = &self.history.moves()[..self.history.cursor()];
+                let budget = Duration::from_secs_f32(self.ai_seconds);
+                engine.request_move(start, moves, player, budget)?;
+            }
+        }
+        engine.poll_move().transpose()
+    }
+
+    /// Asks the alpha-beta engine for a turn without playing it
+    fn request_hint(&mut self) {
+        let budget = Duration::from_secs_f32(self.ai_seconds);
+        self.hint = None;
+        self.hint_search = Some(SearchHandle::spawn(
+            AlphaBeta::new(budget),
+            self.board.clone(),
+        ));
+    }
+
+    fn update_hint(&mut self) {
+        let result = self.hint_search.as_ref().and_then(SearchHandle::try_result);
+        if let Some(mv) = result {
+            self.hint_search = None;
+            self.hint = mv;
+        }
+    }
+
+    fn start_search(&self) -> SearchHandle {
+        let budget = Duration::from_secs_f32(self.ai_seconds);
+        let board = self.board.clone();
+        match self.player_kind(self.board.current_player()) {
+            PlayerKind::Mcts => {
+                let limit = match self.mcts_playouts {
+                    Some(playouts) => Limit::Playouts(playouts as u64),
+                    None => Limit::Time(budget),
+                };
+                SearchHandle::spawn(Mcts::new(limit, self.mcts_exploration), board)
+            }
+            _ => SearchHandle::spawn(AlphaBeta::new(budget), board),
+        }
+    }
+}
+
+impl<E: AmazonsEngine + Clone + Default + Send + 'static> epi::App for AmazonsGame<E> {
     fn update(&mut self, ctx: &egui::CtxRef, frame: &epi::Frame) {
+        // Text fields have their own undo, so the shortcuts are left to them
+        let typing = ctx.wants_keyboard_input();
+        if let ClickableState::GameInProgress | ClickableState::GameOver(_) = self.state {
+            let input = ctx.input();
+            let z = !typing && input.modifiers.command && input.key_pressed(Key::Z);
+            let y = !typing && input.modifiers.command && input.key_pressed(Key::Y);
+            if z && !input.modifiers.shift {
+                self.undo_turn();
+            } else if y || (z && input.modifiers.shift) {
+                self.redo_turn();
+            }
+        }
+
+        self.update_network();
+        self.update_search();
+        self.update_hint();
+
         egui::SidePanel::left("side_panel").show(ctx, |ui| {
             match &self.state {
                 ClickableState::GameInProgress => {
                     ui.heading("Game In Progress");
-                    if self.boardstate.currentPlayer == SquareState_WHITE {
+                    if self.board.current_player() == Player::White {
                         ui.label("Bows to move");
                     } else {
                         ui.label("Spears to move");
                     }
-                    if ui.button("Undo last selection").clicked() {
-                        if self.clicked_square > 0 {
-                            self.clicked_square -= 1;
+                    self.rule_message(ui);
+                    if ui.button("Undo last selection").clicked() && self.clicked_square > 0 {
+                        self.clicked_square -= 1;
+                    }
+                    self.history_buttons(ui);
+                    if let Some(search) = &self.search {
+                        ui.label(format!(
+                            "Thinking… {:.1} s, {} nodes",
+                            search.elapsed().as_secs_f32(),
+                            search.nodes()
+                        ));
+                    }
+                    if self.engine_thinking() {
+                        ui.label("Waiting for the engine…");
+                    }
+                    if let Some(session) = &self.session {
+                        let peer = session.peer_name().unwrap_or_default();
+                        if session.local_player().is_some() {
+                            ui.label(format!("Playing against {} over the network", peer));
+                        } else {
+                            ui.label(format!("Watching {}", peer));
+                        }
+                    }
+                    if let Some(message) = &self.net_message {
+                        ui.colored_label(Color32::RED, message);
+                    }
+                    if let Some(message) = &self.engine_message {
+                        ui.colored_label(Color32::RED, message);
+                    }
+                    ui.checkbox(&mut self.show_territory, "Show territory estimate");
+                    if self.show_territory {
+                        self.territory_estimate(ui);
+                    }
+                    if !self.computer_to_move() {
+                        let idle = self.hint_search.is_none();
+                        if ui.add_enabled(idle, Button::new("Hint")).clicked() {
+                            self.request_hint();
+                        }
+                        if !idle {
+                            ui.label("Looking for a hint…");
+                        }
+                        if let Some(hint) = self.hint {
+                            ui.label(format!("Hint: {}", hint));
                         }
                     }
                     if ui.button("Stop Game").clicked() {
+                        self.cancel_search();
+                        self.clear_hint();
+                        self.leave_network();
                         self.state = ClickableState::Idle;
                     }
                 }
-                #[allow(non_upper_case_globals)]
                 ClickableState::GameOver(winner) => {
                     ui.heading("Game Over!");
                     match *winner {
-                        SquareState_WHITE => {
+                        Player::White => {
                             ui.label("Bows win!");
                             if self.highlight_regions {
                                 ui.label(format!(
@@ -418,7 +1613,7 @@ impl epi::App for AmazonsGame {
                                 ));
                             }
                         }
-                        SquareState_BLACK => {
+                        Player::Black => {
                             ui.label("Spears win!");
                             if self.highlight_regions {
                                 ui.label(format!(
@@ -427,9 +1622,13 @@ impl epi::App for AmazonsGame {
                                 ));
                             }
                         }
-                        _ => (),
                     }
+                    if let Some(message) = &self.engine_message {
+                        ui.label(message);
+                    }
+                    self.history_buttons(ui);
                     if ui.button("OK").clicked() {
+                        self.leave_network();
                         self.state = ClickableState::Idle;
                     }
                 }
@@ -440,6 +1639,32 @@ impl epi::App for AmazonsGame {
                     number_setting(ui, &mut self.black_amazons, 0, 10, "Player 2 pieces");
                     number_setting(ui, &mut self.board_width, 2, 20, "Board width");
                     number_setting(ui, &mut self.board_height, 2, 20, "Board height");
+                    player_setting(
+                        ui,
+                        &mut self.white_player,
+                        &mut self.white_engine_path,
+                        "Player 1",
+                    );
+                    player_setting(
+                        ui,
+                        &mut self.black_player,
+                        &mut self.black_engine_path,
+                        "Player 2",
+                    );
+                    let seconds = Slider::new(&mut self.ai_seconds, 0.1..=30.)
+                        .logarithmic(true)
+                        .text("Computer seconds per move");
+                    ui.add(seconds);
+                    self.mcts_settings(ui);
+
+                    ui.horizontal(|ui| {
+                        ui.label("Player 1 name");
+                        ui.text_edit_singleline(&mut self.white_name);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Player 2 name");
+                        ui.text_edit_singleline(&mut self.black_name);
+                    });
 
                     if ui.button("Set player 1 starting positions").clicked() {
                         self.white_starting.clear();
@@ -458,6 +1683,26 @@ impl epi::App for AmazonsGame {
                     if ui.button("New Game").clicked() {
                         self.new_game();
                     }
+                    self.network_settings(ui);
+                }
+                ClickableState::Joining => self.join_dialog(ui),
+                ClickableState::Lobby => self.lobby(ui),
+                ClickableState::Connecting => {
+                    ui.heading("Network Game");
+                    if let Some(port) = self.listener.as_ref().and_then(Listener::port) {
+                        ui.label(format!("Waiting for a player to join on port {}", port));
+                    } else if self.dialer.is_some() {
+                        ui.label(format!("Connecting to {}", self.net_address.trim()));
+                    } else {
+                        ui.label("Waiting for the host to start the game");
+                    }
+                    if let Some(message) = &self.net_message {
+                        ui.colored_label(Color32::RED, message);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.leave_network();
+                        self.state = ClickableState::Idle;
+                    }
                 }
                 ClickableState::PickingWhite | ClickableState::PickingBlack => {
                     ui.heading("Pick Starting Locations");
@@ -493,12 +1738,23 @@ impl epi::App for AmazonsGame {
                 }
             }
 
+            if let ClickableState::Idle
+            | ClickableState::GameInProgress
+            | ClickableState::GameOver(_) = self.state
+            {
+                self.file_controls(ui);
+            }
+
             let sep = Separator::default().spacing(12.).horizontal();
             ui.add(sep);
 
             if ui.button("Quit").clicked() {
                 frame.quit();
             }
+
+            if let ClickableState::GameInProgress | ClickableState::GameOver(_) = self.state {
+                self.move_list(ui);
+            }
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
@@ -516,44 +1772,39 @@ impl epi::App for AmazonsGame {
                     let x = (canvas_pos.x / square_size).floor() as u32;
                     let y = (canvas_pos.y / square_size).floor() as u32;
                     match self.state {
-                        ClickableState::GameInProgress => {
-                            let acceptable = match self.clicked_square {
+                        ClickableState::GameInProgress
+                            if self.viewing.is_none() && !self.computer_to_move() =>
+                        {
+                            let accepted = match self.clicked_square {
                                 0 => self.set_src(x, y),
                                 1 => self.set_dst(x, y),
-                                _ => {
-                                    if self.move_amazon(x, y) {
-                                        let winner = self.game_winner();
-                                        #[allow(non_upper_case_globals)]
-                                        match winner {
-                                            SquareState_WHITE | SquareState_BLACK => {
-                                                self.state = ClickableState::GameOver(winner)
-                                            }
-                                            _ => (),
-                                        }
-                                        true
-                                    } else {
-                                        false
-                                    }
-                                }
+                                _ => self.move_amazon(x, y),
                             };
-                            if acceptable {
-                                self.clicked_square = (self.clicked_square + 1) % 3;
+                            match accepted {
+                                Ok(()) => {
+                                    self.clicked_square = (self.clicked_square + 1) % 3;
+                                    self.rule_message = None;
+                                }
+                                Err(e) => {
+                                    let message = capitalize(&e.to_string());
+                                    self.rule_message = Some((message, Instant::now()));
+                                }
                             }
                         }
-                        ClickableState::PickingWhite => {
-                            if x < self.board_width && y < self.board_height {
-                                self.white_starting.push((x, y));
-                                if self.white_starting.len() == self.white_amazons as usize {
-                                    self.state = ClickableState::Idle;
-                                }
+                        ClickableState::PickingWhite
+                            if x < self.board_width && y < self.board_height =>
+                        {
+                            self.white_starting.push((x, y));
+                            if self.white_starting.len() == self.white_amazons as usize {
+                                self.state = ClickableState::Idle;
                             }
                         }
-                        ClickableState::PickingBlack => {
-                            if x < self.board_width && y < self.board_height {
-                                self.black_starting.push((x, y));
-                                if self.black_starting.len() == self.black_amazons as usize {
-                                    self.state = ClickableState::Idle;
-                                }
+                        ClickableState::PickingBlack
+                            if x < self.board_width && y < self.board_height =>
+                        {
+                            self.black_starting.push((x, y));
+                            if self.black_starting.len() == self.black_amazons as usize {
+                                self.state = ClickableState::Idle;
                             }
                         }
                         _ => (),
@@ -562,6 +1813,17 @@ impl epi::App for AmazonsGame {
             }
             self.draw_board(&painter, to_screen, frame);
         });
+
+        // Keep the thinking indicator running and pick up the result without
+        // waiting for input
+        let waiting = self.search.is_some() || self.hint_search.is_some() || self.engine_thinking();
+        let networking = self.session.is_some()
+            || self.listener.is_some()
+            || self.dialer.is_some()
+            || self.browser.is_some();
+        if waiting || networking || self.computer_to_move() || self.rule_message.is_some() {
+            ctx.request_repaint();
+        }
     }
 
     fn setup(
@@ -574,13 +1836,19 @@ impl epi::App for AmazonsGame {
         // Note that you must enable the `persistence` feature for this to work.
         #[cfg(feature = "persistence")]
         if let Some(storage) = _storage {
-            *self = epi::get_value(storage, epi::APP_KEY).unwrap_or_default()
+            *self = epi::get_value(storage, epi::APP_KEY).unwrap_or_default();
+            // Resume the game that was in progress when the app was closed
+            let saved = epi::get_value::<Option<SavedGame>>(storage, GAME_KEY).flatten();
+            if let Some(Err(e)) = saved.map(|saved| self.restore_game(saved)) {
+                self.file_message = Some(format!("Could not resume previous game: {}", e));
+            }
         }
     }
 
     #[cfg(feature = "persistence")]
     fn save(&mut self, storage: &mut dyn epi::Storage) {
         epi::set_value(storage, epi::APP_KEY, self);
+        epi::set_value(storage, GAME_KEY, &self.saved_game());
     }
 
     fn name(&self) -> &str {
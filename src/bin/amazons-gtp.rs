@@ -0,0 +1,62 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use amazons::board::Board;
+use amazons::gtp::GtpEngine;
+use std::io::{self, BufRead, Write};
+use std::process::exit;
+
+const USAGE: &str =
+    "Usage: amazons-gtp\n\nReads engine protocol commands from standard input, one per line.";
+
+fn main() {
+    if let Some(arg) = std::env::args().nth(1) {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(1);
+            }
+        }
+    }
+
+    let mut engine = GtpEngine::<Board>::default();
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Failed to read input: {}", e);
+                exit(1);
+            }
+        };
+        if let Some(response) = engine.execute(&line) {
+            // The controller may have gone away; there is nobody left to answer
+            if stdout
+                .write_all(response.as_bytes())
+                .and_then(|_| stdout.flush())
+                .is_err()
+            {
+                return;
+            }
+        }
+        if engine.finished() {
+            return;
+        }
+    }
+}
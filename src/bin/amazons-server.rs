@@ -0,0 +1,54 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use amazons::board::Board;
+use amazons::net::DEFAULT_PORT;
+use amazons::server::Server;
+use std::process::exit;
+
+const USAGE: &str = "Usage: amazons-server [--port <port>]";
+
+fn main() {
+    let mut port = DEFAULT_PORT;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-p" | "--port" => match args.next().map(|p| p.parse::<u16>()) {
+                Some(Ok(p)) => port = p,
+                _ => {
+                    eprintln!("{}", USAGE);
+                    exit(1);
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(1);
+            }
+        }
+    }
+
+    let mut server = match Server::<Board>::bind(port) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Could not listen on port {}: {}", port, e);
+            exit(1);
+        }
+    };
+    println!("Listening on port {}", server.port().unwrap_or(port));
+    server.run();
+}
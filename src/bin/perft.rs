@@ -0,0 +1,94 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use amazons::board::Board;
+use amazons::engine::AmazonsEngine;
+use amazons::perft::{divide, perft};
+use amazons::position::Position;
+use std::process::exit;
+use std::time::Instant;
+
+const USAGE: &str = "Usage: perft [--divide] [--position <position>] <depth>";
+
+fn main() {
+    let mut depth = None;
+    let mut split = false;
+    let mut position = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--divide" => split = true,
+            "-p" | "--position" => match args.next().map(|p| p.parse::<Position>()) {
+                Some(Ok(p)) => position = Some(p),
+                Some(Err(e)) => {
+                    eprintln!("Invalid position: {}", e);
+                    exit(1);
+                }
+                None => {
+                    eprintln!("{}", USAGE);
+                    exit(1);
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => match arg.parse::<u32>() {
+                Ok(d) if depth.is_none() => depth = Some(d),
+                _ => {
+                    eprintln!("{}", USAGE);
+                    exit(1);
+                }
+            },
+        }
+    }
+    let depth = match depth {
+        Some(depth) => depth,
+        None => {
+            eprintln!("{}", USAGE);
+            exit(1);
+        }
+    };
+    if split && depth == 0 {
+        eprintln!("--divide needs a depth of at least 1");
+        exit(1);
+    }
+
+    let board = match position.map(|p| p.to_engine::<Board>()) {
+        None => Board::standard(),
+        Some(Ok(board)) => board,
+        Some(Err(e)) => {
+            eprintln!("Invalid position: {}", e);
+            exit(1);
+        }
+    };
+    let start = Instant::now();
+    let nodes = if split {
+        let mut nodes = 0;
+        for (mv, count) in divide(&board, depth) {
+            println!("{}: {}", mv, count);
+            nodes += count;
+        }
+        nodes
+    } else {
+        perft(&board, depth)
+    };
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("Nodes: {}", nodes);
+    println!(
+        "Time: {:.3}s ({:.0} nodes/s)",
+        elapsed,
+        nodes as f64 / elapsed.max(f64::EPSILON)
+    );
+}
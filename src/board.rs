@@ -0,0 +1,361 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::boardstate::Amazons::*;
+use crate::engine::{check_starting, AmazonsEngine};
+use std::fmt;
+
+/// One of the two sides in a game; white moves first
+#[cfg_attr(feature = "persistence", derive(serde::Deserialize, serde::Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Player {
+    White,
+    Black,
+}
+
+impl Player {
+    pub fn opponent(self) -> Self {
+        match self {
+            Player::White => Player::Black,
+            Player::Black => Player::White,
+        }
+    }
+
+    #[allow(non_upper_case_globals)]
+    fn from_state(state: SquareState) -> Option<Self> {
+        match state {
+            SquareState_WHITE => Some(Player::White),
+            SquareState_BLACK => Some(Player::Black),
+            _ => None,
+        }
+    }
+
+    fn to_state(self) -> SquareState {
+        match self {
+            Player::White => SquareState_WHITE,
+            Player::Black => SquareState_BLACK,
+        }
+    }
+}
+
+/// Contents of a single square on the board
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cell {
+    Empty,
+    Arrow,
+    Amazon(Player),
+}
+
+/// A square on the board; `x` is the column and `y` the row, both zero-based.
+/// Every `Board` method checks coordinates against its own dimensions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Coord {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Coord {
+    pub fn new(x: u32, y: u32) -> Self {
+        Coord { x, y }
+    }
+
+    fn to_square(self) -> Square {
+        Square::new(self.x, self.y)
+    }
+}
+
+/// Reasons for which the board rejects an operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoardError {
+    OutOfBounds(Coord),
+    Overlapping(Coord),
+    NotOwnPiece(Coord),
+    IllegalMove(Coord, Coord),
+    IllegalShot(Coord, Coord),
+    /// The squares are not on a common row, column or diagonal
+    NotQueenLine(Coord, Coord),
+    /// A piece or arrow stands between the start and the target
+    Blocked(Coord),
+    Occupied(Coord),
+    GameNotOver,
+    GameOver,
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoardError::OutOfBounds(c) => write!(f, "{} is out of bounds", c),
+            BoardError::Overlapping(c) => write!(f, "more than one piece placed at {}", c),
+            BoardError::NotOwnPiece(c) => write!(f, "no amazon of the player to move at {}", c),
+            BoardError::IllegalMove(src, dst) => write!(f, "cannot move from {} to {}", src, dst),
+            BoardError::IllegalShot(src, dst) => write!(f, "cannot shoot from {} to {}", src, dst),
+            BoardError::NotQueenLine(src, dst) => write!(
+                f,
+                "{} and {} are not on the same row, column or diagonal",
+                src, dst
+            ),
+            BoardError::Blocked(c) => write!(f, "the path is blocked at {}", c),
+            BoardError::Occupied(c) => write!(f, "{} is already occupied", c),
+            BoardError::GameNotOver => write!(f, "the game is not over"),
+            BoardError::GameOver => write!(f, "the game is already over"),
+        }
+    }
+}
+
+impl std::error::Error for BoardError {}
+
+/// Result of a finished game. `territory` holds the number of squares
+/// controlled by white and black if the game was decided by the board
+/// being partitioned rather than by a player running out of moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outcome {
+    pub winner: Player,
+    pub territory: Option<(u32, u32)>,
+}
+
+/// Safe wrapper around the board state of the Amazons library, backed by
+/// either the C library or the `pure-rust` implementation
+#[derive(Debug, Default, Clone)]
+pub struct Board {
+    state: BoardState,
+}
+
+// The board exclusively owns the buffers allocated by the library, which
+// keeps no other state, so it can be handed to another thread
+unsafe impl Send for Board {}
+
+impl Board {
+    fn check(&self, coord: Coord) -> Result<(), BoardError> {
+        self.coord(coord.x, coord.y).map(|_| ())
+    }
+
+    // The library takes mutable pointers even for queries that only read the
+    // board. The pointer must only be passed to those queries; anything that
+    // writes to the state (including the region map) goes through `&mut self`.
+    fn query_ptr(&self) -> *mut BoardState {
+        &self.state as *const BoardState as *mut BoardState
+    }
+}
+
+impl AmazonsEngine for Board {
+    fn standard() -> Self {
+        let mut board = Board::default();
+        unsafe {
+            boardstate_standard(&mut board.state);
+        }
+        board
+    }
+
+    fn new(
+        width: u32,
+        height: u32,
+        white_starting: &[(u32, u32)],
+        black_starting: &[(u32, u32)],
+    ) -> Result<Self, BoardError> {
+        check_starting(width, height, white_starting, black_starting)?;
+        let mut board = Board::default();
+        board.state.init(
+            white_starting.len() as u32,
+            black_starting.len() as u32,
+            width,
+            height,
+            white_starting,
+            black_starting,
+        );
+        Ok(board)
+    }
+
+    fn width(&self) -> u32 {
+        self.state.boardWidth as u32
+    }
+
+    fn height(&self) -> u32 {
+        self.state.boardHeight as u32
+    }
+
+    fn current_player(&self) -> Player {
+        Player::from_state(self.state.currentPlayer).unwrap_or(Player::White)
+    }
+
+    fn swap_player(&mut self) {
+        unsafe {
+            swapPlayer(&mut self.state.currentPlayer);
+        }
+    }
+
+    #[allow(non_upper_case_globals)]
+    fn cell(&self, coord: Coord) -> Result<Cell, BoardError> {
+        self.check(coord)?;
+        let mut sq = coord.to_square();
+        let state = unsafe { boardstate_squareState(self.query_ptr(), &mut sq) };
+        Ok(match state {
+            SquareState_ARROW => Cell::Arrow,
+            state => Player::from_state(state).map_or(Cell::Empty, Cell::Amazon),
+        })
+    }
+
+    fn controller(&self, coord: Coord) -> Result<Option<Player>, BoardError> {
+        self.check(coord)?;
+        let mut sq = coord.to_square();
+        let state = unsafe { boardstate_squareController(self.query_ptr(), &mut sq) };
+        Ok(Player::from_state(state))
+    }
+
+    fn is_valid_move(&self, src: Coord, dst: Coord) -> bool {
+        if !self.contains(src) || !self.contains(dst) {
+            return false;
+        }
+        let (mut src, mut dst) = (src.to_square(), dst.to_square());
+        unsafe { isValidMove(self.query_ptr(), &mut src, &mut dst) == 1 }
+    }
+
+    fn has_valid_move(&self, player: Player) -> bool {
+        unsafe { playerHasValidMove(self.query_ptr(), player.to_state()) == 1 }
+    }
+
+    fn move_amazon(&mut self, src: Coord, dst: Coord) -> Result<(), BoardError> {
+        if self.cell(src)? != Cell::Amazon(self.current_player()) {
+            return Err(BoardError::NotOwnPiece(src));
+        }
+        self.check(dst)?;
+        let (mut src_sq, mut dst_sq) = (src.to_square(), dst.to_square());
+        if unsafe { amazons_move(&mut self.state, &mut src_sq, &mut dst_sq) } == 1 {
+            Ok(())
+        } else {
+            Err(BoardError::IllegalMove(src, dst))
+        }
+    }
+
+    fn shoot(&mut self, src: Coord, target: Coord) -> Result<(), BoardError> {
+        self.check(src)?;
+        self.check(target)?;
+        let (mut src_sq, mut target_sq) = (src.to_square(), target.to_square());
+        if unsafe { amazons_shoot(&mut self.state, &mut src_sq, &mut target_sq) } == 1 {
+            Ok(())
+        } else {
+            Err(BoardError::IllegalShot(src, target))
+        }
+    }
+
+    fn place_arrow(&mut self, coord: Coord) -> Result<(), BoardError> {
+        if self.cell(coord)? != Cell::Empty {
+            return Err(BoardError::Overlapping(coord));
+        }
+        // The library stores the squares row by row
+        let index = (coord.y * self.width() + coord.x) as usize;
+        unsafe {
+            *self.state.board.add(index) = SquareState_ARROW;
+        }
+        Ok(())
+    }
+
+    fn winner(&mut self) -> Result<Outcome, BoardError> {
+        let (mut ws, mut bs) = (0, 0);
+        let (winner, moves_left) = unsafe {
+            (
+                boardstate_winner(&mut self.state, &mut ws, &mut bs),
+                playerHasValidMove(&mut self.state, self.state.currentPlayer) == 1,
+            )
+        };
+        if let Some(winner) = Player::from_state(winner) {
+            let winner = if ws == bs { winner.opponent() } else { winner };
+            let territory = if moves_left {
+                Some((ws as u32, bs as u32))
+            } else {
+                None
+            };
+            return Ok(Outcome { winner, territory });
+        }
+        if moves_left {
+            return Err(BoardError::GameNotOver);
+        }
+        Ok(Outcome {
+            winner: self.current_player().opponent(),
+            territory: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn out_of_bounds_squares_are_rejected() {
+        let mut board = Board::standard();
+        let outside = Coord::new(10, 3);
+        assert_eq!(board.cell(outside), Err(BoardError::OutOfBounds(outside)));
+        assert_eq!(
+            board.controller(outside),
+            Err(BoardError::OutOfBounds(outside))
+        );
+        assert!(!board.is_valid_move(Coord::new(3, 0), outside));
+        assert_eq!(
+            board.move_amazon(Coord::new(3, 0), outside),
+            Err(BoardError::OutOfBounds(outside))
+        );
+        assert_eq!(
+            board.place_arrow(Coord::new(0, 10)),
+            Err(BoardError::OutOfBounds(Coord::new(0, 10)))
+        );
+        assert_eq!(
+            Board::new(3, 3, &[(0, 0)], &[(3, 0)]).unwrap_err(),
+            BoardError::OutOfBounds(Coord::new(3, 0))
+        );
+    }
+
+    #[test]
+    fn only_own_amazons_move() {
+        let mut board = Board::standard();
+        let black = Coord::new(6, 0);
+        assert_eq!(
+            board.play(black, Coord::new(6, 5), Coord::new(6, 6)),
+            Err(BoardError::NotOwnPiece(black))
+        );
+        let empty = Coord::new(4, 4);
+        assert_eq!(
+            board.move_amazon(empty, Coord::new(4, 5)),
+            Err(BoardError::NotOwnPiece(empty))
+        );
+        assert_eq!(board.current_player(), Player::White);
+        assert_eq!(board.cell(black), Ok(Cell::Amazon(Player::Black)));
+    }
+
+    #[test]
+    fn winner_once_a_player_is_stuck() {
+        let mut board = Board::new(4, 1, &[(0, 0)], &[(3, 0)]).unwrap();
+        assert_eq!(board.winner(), Err(BoardError::GameNotOver));
+        board
+            .play(Coord::new(0, 0), Coord::new(1, 0), Coord::new(2, 0))
+            .unwrap();
+        // Black has no move left, regardless of territory
+        let outcome = board.winner().unwrap();
+        assert_eq!(outcome.winner, Player::White);
+        assert_eq!(outcome.territory, None);
+        assert_eq!(board.controller(Coord::new(0, 0)), Ok(Some(Player::White)));
+    }
+
+    #[test]
+    fn dropping_a_clone_keeps_the_original() {
+        let mut board = Board::standard();
+        let copy = board.clone();
+        drop(copy);
+        board
+            .play(Coord::new(3, 0), Coord::new(3, 5), Coord::new(3, 2))
+            .unwrap();
+        assert_eq!(board.cell(Coord::new(3, 2)), Ok(Cell::Arrow));
+        // Boards that were never initialised own no buffers
+        drop(Board::default());
+    }
+}
@@ -68,6 +68,36 @@ pub mod Amazons {
         }
     }
 
+    impl Clone for BoardState {
+        /// Copies the board and region map into newly allocated buffers
+        fn clone(&self) -> Self {
+            let mut copy = BoardState::default();
+            if self.board.is_null() {
+                return copy;
+            }
+            // Let the library allocate the buffers so that they can be freed on drop
+            copy.init(
+                0,
+                0,
+                self.boardWidth as u32,
+                self.boardHeight as u32,
+                &[],
+                &[],
+            );
+            let size = (self.boardWidth * self.boardHeight) as usize;
+            unsafe {
+                ptr::copy_nonoverlapping(self.board, copy.board, size);
+                if !self.map.is_null() && !copy.map.is_null() {
+                    ptr::copy_nonoverlapping(self.map, copy.map, size);
+                }
+            }
+            copy.whitePieces = self.whitePieces;
+            copy.blackPieces = self.blackPieces;
+            copy.currentPlayer = self.currentPlayer;
+            copy
+        }
+    }
+
     impl BoardState {
         pub fn init(
             &mut self,
@@ -75,8 +105,8 @@ pub mod Amazons {
             black_pieces: u32,
             board_width: u32,
             board_height: u32,
-            white_starting: &Vec<(u32, u32)>,
-            black_starting: &Vec<(u32, u32)>,
+            white_starting: &[(u32, u32)],
+            black_starting: &[(u32, u32)],
         ) {
             let mut white: Vec<Square> = white_starting
                 .iter()
@@ -100,6 +130,32 @@ pub mod Amazons {
         }
     }
 
+    #[test]
+    fn clone_is_deep() {
+        let mut board = BoardState::default();
+        board.init(1, 1, 3, 3, &[(0, 0)], &[(2, 2)]);
+        let mut copy = board.clone();
+        assert_ne!(board.board, copy.board);
+        let (mut src, mut dst) = (Square::new(0, 0), Square::new(1, 1));
+        unsafe {
+            assert_eq!(amazons_move(&mut board, &mut src, &mut dst), 1);
+            assert_eq!(
+                boardstate_squareState(&mut copy, &mut src),
+                SquareState_WHITE
+            );
+            assert_eq!(
+                boardstate_squareState(&mut copy, &mut dst),
+                SquareState_EMPTY
+            );
+        }
+        drop(board);
+        let mut corner = Square::new(2, 2);
+        assert_eq!(
+            unsafe { boardstate_squareState(&mut copy, &mut corner) },
+            SquareState_BLACK
+        );
+    }
+
     #[test]
     fn bindgen_test_layout_BoardState() {
         assert_eq!(
@@ -113,7 +169,7 @@ pub mod Amazons {
             concat!("Alignment of ", stringify!(BoardState))
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<BoardState>())).whitePieces as *const _ as usize },
+            ::std::mem::offset_of!(BoardState, whitePieces),
             0usize,
             concat!(
                 "Offset of field: ",
@@ -123,7 +179,7 @@ pub mod Amazons {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<BoardState>())).blackPieces as *const _ as usize },
+            ::std::mem::offset_of!(BoardState, blackPieces),
             4usize,
             concat!(
                 "Offset of field: ",
@@ -133,7 +189,7 @@ pub mod Amazons {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<BoardState>())).boardWidth as *const _ as usize },
+            ::std::mem::offset_of!(BoardState, boardWidth),
             8usize,
             concat!(
                 "Offset of field: ",
@@ -143,7 +199,7 @@ pub mod Amazons {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<BoardState>())).boardHeight as *const _ as usize },
+            ::std::mem::offset_of!(BoardState, boardHeight),
             12usize,
             concat!(
                 "Offset of field: ",
@@ -153,7 +209,7 @@ pub mod Amazons {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<BoardState>())).currentPlayer as *const _ as usize },
+            ::std::mem::offset_of!(BoardState, currentPlayer),
             16usize,
             concat!(
                 "Offset of field: ",
@@ -163,7 +219,7 @@ pub mod Amazons {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<BoardState>())).board as *const _ as usize },
+            ::std::mem::offset_of!(BoardState, board),
             24usize,
             concat!(
                 "Offset of field: ",
@@ -173,7 +229,7 @@ pub mod Amazons {
             )
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<BoardState>())).map as *const _ as usize },
+            ::std::mem::offset_of!(BoardState, map),
             32usize,
             concat!(
                 "Offset of field: ",
@@ -186,18 +242,12 @@ pub mod Amazons {
 
     #[doc = " Represents a square on the board"]
     #[repr(C)]
-    #[derive(Debug, Copy, Clone)]
+    #[derive(Debug, Default, Copy, Clone)]
     pub struct Square {
         pub x: ::std::os::raw::c_int,
         pub y: ::std::os::raw::c_int,
     }
 
-    impl Default for Square {
-        fn default() -> Self {
-            Square { x: 0, y: 0 }
-        }
-    }
-
     impl Square {
         pub fn new(x: u32, y: u32) -> Self {
             Square {
@@ -224,140 +274,154 @@ pub mod Amazons {
             concat!("Alignment of ", stringify!(Square))
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<Square>())).x as *const _ as usize },
+            ::std::mem::offset_of!(Square, x),
             0usize,
             concat!("Offset of field: ", stringify!(Square), "::", stringify!(x))
         );
         assert_eq!(
-            unsafe { &(*(::std::ptr::null::<Square>())).y as *const _ as usize },
+            ::std::mem::offset_of!(Square, y),
             4usize,
             concat!("Offset of field: ", stringify!(Square), "::", stringify!(y))
         );
     }
 
-    extern "C" {
-        #[doc = " Swaps the given player to the other player"]
-        #[doc = " @param player Player variable to swap"]
-        pub fn swapPlayer(player: *mut SquareState);
-    }
+    #[cfg(not(feature = "pure-rust"))]
+    pub use ffi::*;
 
-    extern "C" {
-        #[doc = " Initializes a board with the given game settings"]
-        #[doc = " @param board Board state to initialize"]
-        #[doc = " @param wp Number of white/player 1 pieces"]
-        #[doc = " @param bp Number of black/player 2 pieces"]
-        #[doc = " @param bw Board width"]
-        #[doc = " @param bh Board height"]
-        #[doc = " @param whiteStart Array of Squares indicating starting locations for white player/player 1"]
-        #[doc = " @Param blackStart Array of Squares indicating starting locations for black player/player 2"]
-        pub fn boardstate_init(
-            board: *mut BoardState,
-            wp: ::std::os::raw::c_int,
-            bp: ::std::os::raw::c_int,
-            bw: ::std::os::raw::c_int,
-            bh: ::std::os::raw::c_int,
-            whiteStart: *mut Square,
-            blackStart: *mut Square,
-        );
-    }
+    #[cfg(feature = "pure-rust")]
+    pub use crate::native::*;
 
-    extern "C" {
-        #[doc = " Initializes a board with tournament standard configuration"]
-        #[doc = " @param board Board state to initialize"]
-        pub fn boardstate_standard(board: *mut BoardState);
-    }
+    #[cfg(any(not(feature = "pure-rust"), feature = "differential"))]
+    pub mod ffi {
+        use super::{BoardState, Square, SquareState};
 
-    extern "C" {
-        #[doc = " Deallocates the memory occupied by a board"]
-        #[doc = " @param board Board state"]
-        pub fn boardstate_free(board: *mut BoardState);
-    }
+        extern "C" {
+            #[doc = " Swaps the given player to the other player"]
+            #[doc = " @param player Player variable to swap"]
+            pub fn swapPlayer(player: *mut SquareState);
+        }
 
-    extern "C" {
-        #[doc = " Determines whether a player has won the game based on the number of"]
-        #[doc = " squares controlled by each player. This function must be called"]
-        #[doc = " while the current player for the board state is still set to the player"]
-        #[doc = " who made the last move, not the player who will make the next move;"]
-        #[doc = " note that this function does not replace playerHasValidMove: the game"]
-        #[doc = " can end without the map being definitively divided"]
-        #[doc = " @param board Board state to check"]
-        #[doc = " @param white Where to store the number of squares controlled by white"]
-        #[doc = " @param black Where to store the number of squares controlled by black"]
-        #[doc = " @return The winning player, or EMPTY if neither player has won"]
-        pub fn boardstate_winner(
-            board: *mut BoardState,
-            white: *mut ::std::os::raw::c_int,
-            black: *mut ::std::os::raw::c_int,
-        ) -> SquareState;
-    }
+        extern "C" {
+            #[doc = " Initializes a board with the given game settings"]
+            #[doc = " @param board Board state to initialize"]
+            #[doc = " @param wp Number of white/player 1 pieces"]
+            #[doc = " @param bp Number of black/player 2 pieces"]
+            #[doc = " @param bw Board width"]
+            #[doc = " @param bh Board height"]
+            #[doc = " @param whiteStart Array of Squares indicating starting locations for white player/player 1"]
+            #[doc = " @Param blackStart Array of Squares indicating starting locations for black player/player 2"]
+            pub fn boardstate_init(
+                board: *mut BoardState,
+                wp: ::std::os::raw::c_int,
+                bp: ::std::os::raw::c_int,
+                bw: ::std::os::raw::c_int,
+                bh: ::std::os::raw::c_int,
+                whiteStart: *mut Square,
+                blackStart: *mut Square,
+            );
+        }
 
-    extern "C" {
-        #[doc = " Determines the state of a given square on the board"]
-        #[doc = " @param board Board state to check"]
-        #[doc = " @param square Square to check"]
-        #[doc = " @return The state of the given square"]
-        pub fn boardstate_squareState(board: *mut BoardState, square: *mut Square) -> SquareState;
-    }
+        extern "C" {
+            #[doc = " Initializes a board with tournament standard configuration"]
+            #[doc = " @param board Board state to initialize"]
+            pub fn boardstate_standard(board: *mut BoardState);
+        }
 
-    extern "C" {
-        #[doc = " Determines who controls the region containing a given square on the board;"]
-        #[doc = " the map should be updated before calling this function"]
-        #[doc = " @param board Board state to check"]
-        #[doc = " @param square Square to check"]
-        #[doc = " @return The controller of the region containing the given square"]
-        pub fn boardstate_squareController(
-            board: *mut BoardState,
-            square: *mut Square,
-        ) -> SquareState;
-    }
+        extern "C" {
+            #[doc = " Deallocates the memory occupied by a board"]
+            #[doc = " @param board Board state"]
+            pub fn boardstate_free(board: *mut BoardState);
+        }
 
-    extern "C" {
-        #[doc = " Determines whether a player has any valid moves"]
-        #[doc = " @param board Board state"]
-        #[doc = " @param player The player to check"]
-        #[doc = " @return Whether the specified player has any valid moves"]
-        pub fn playerHasValidMove(
-            board: *mut BoardState,
-            player: SquareState,
-        ) -> ::std::os::raw::c_int;
-    }
+        extern "C" {
+            #[doc = " Determines whether a player has won the game based on the number of"]
+            #[doc = " squares controlled by each player. This function must be called"]
+            #[doc = " while the current player for the board state is still set to the player"]
+            #[doc = " who made the last move, not the player who will make the next move;"]
+            #[doc = " note that this function does not replace playerHasValidMove: the game"]
+            #[doc = " can end without the map being definitively divided"]
+            #[doc = " @param board Board state to check"]
+            #[doc = " @param white Where to store the number of squares controlled by white"]
+            #[doc = " @param black Where to store the number of squares controlled by black"]
+            #[doc = " @return The winning player, or EMPTY if neither player has won"]
+            pub fn boardstate_winner(
+                board: *mut BoardState,
+                white: *mut ::std::os::raw::c_int,
+                black: *mut ::std::os::raw::c_int,
+            ) -> SquareState;
+        }
 
-    extern "C" {
-        #[doc = " Determine whether a move is valid"]
-        #[doc = " @param board Board state"]
-        #[doc = " @param src Starting location"]
-        #[doc = " @param dst Ending location"]
-        #[doc = " @return Whether the specified squares are on the same row, column, or diagonal"]
-        pub fn isValidMove(
-            board: *mut BoardState,
-            src: *mut Square,
-            dst: *mut Square,
-        ) -> ::std::os::raw::c_int;
-    }
+        extern "C" {
+            #[doc = " Determines the state of a given square on the board"]
+            #[doc = " @param board Board state to check"]
+            #[doc = " @param square Square to check"]
+            #[doc = " @return The state of the given square"]
+            pub fn boardstate_squareState(
+                board: *mut BoardState,
+                square: *mut Square,
+            ) -> SquareState;
+        }
 
-    extern "C" {
-        #[doc = " Move an Amazon"]
-        #[doc = " @param board Board state"]
-        #[doc = " @param src Current location of the Amazon"]
-        #[doc = " @param dst Destination location of the Amazon"]
-        #[doc = " @return Whether the specified move is valid"]
-        pub fn amazons_move(
-            board: *mut BoardState,
-            src: *mut Square,
-            dst: *mut Square,
-        ) -> ::std::os::raw::c_int;
-    }
+        extern "C" {
+            #[doc = " Determines who controls the region containing a given square on the board;"]
+            #[doc = " the map should be updated before calling this function"]
+            #[doc = " @param board Board state to check"]
+            #[doc = " @param square Square to check"]
+            #[doc = " @return The controller of the region containing the given square"]
+            pub fn boardstate_squareController(
+                board: *mut BoardState,
+                square: *mut Square,
+            ) -> SquareState;
+        }
+
+        extern "C" {
+            #[doc = " Determines whether a player has any valid moves"]
+            #[doc = " @param board Board state"]
+            #[doc = " @param player The player to check"]
+            #[doc = " @return Whether the specified player has any valid moves"]
+            pub fn playerHasValidMove(
+                board: *mut BoardState,
+                player: SquareState,
+            ) -> ::std::os::raw::c_int;
+        }
 
-    extern "C" {
-        #[doc = " Shoot an arrow"]
-        #[doc = " @param board Board state"]
-        #[doc = " @param src Current location of the firing Amazon"]
-        #[doc = " @param target Target location of the firing Amazon"]
-        #[doc = " @return Whether the specified shot is valid"]
-        pub fn amazons_shoot(
-            board: *mut BoardState,
-            src: *mut Square,
-            target: *mut Square,
-        ) -> ::std::os::raw::c_int;
+        extern "C" {
+            #[doc = " Determine whether a move is valid"]
+            #[doc = " @param board Board state"]
+            #[doc = " @param src Starting location"]
+            #[doc = " @param dst Ending location"]
+            #[doc = " @return Whether the specified squares are on the same row, column, or diagonal"]
+            pub fn isValidMove(
+                board: *mut BoardState,
+                src: *mut Square,
+                dst: *mut Square,
+            ) -> ::std::os::raw::c_int;
+        }
+
+        extern "C" {
+            #[doc = " Move an Amazon"]
+            #[doc = " @param board Board state"]
+            #[doc = " @param src Current location of the Amazon"]
+            #[doc = " @param dst Destination location of the Amazon"]
+            #[doc = " @return Whether the specified move is valid"]
+            pub fn amazons_move(
+                board: *mut BoardState,
+                src: *mut Square,
+                dst: *mut Square,
+            ) -> ::std::os::raw::c_int;
+        }
+
+        extern "C" {
+            #[doc = " Shoot an arrow"]
+            #[doc = " @param board Board state"]
+            #[doc = " @param src Current location of the firing Amazon"]
+            #[doc = " @param target Target location of the firing Amazon"]
+            #[doc = " @return Whether the specified shot is valid"]
+            pub fn amazons_shoot(
+                board: *mut BoardState,
+                src: *mut Square,
+                target: *mut Square,
+            ) -> ::std::os::raw::c_int;
+        }
     }
 }
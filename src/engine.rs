@@ -0,0 +1,306 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::board::{BoardError, Cell, Coord, Outcome, Player};
+
+/// The rules of the game as seen by the frontend. Implementations provide
+/// the board representation and move validation; everything built on top
+/// of the rules (the GUI, move generation, search) only goes through this trait.
+pub trait AmazonsEngine {
+    /// Creates a board with the tournament standard configuration
+    fn standard() -> Self
+    where
+        Self: Sized;
+
+    /// Creates a board of the given size with the given starting positions
+    fn new(
+        width: u32,
+        height: u32,
+        white_starting: &[(u32, u32)],
+        black_starting: &[(u32, u32)],
+    ) -> Result<Self, BoardError>
+    where
+        Self: Sized;
+
+    fn width(&self) -> u32;
+
+    fn height(&self) -> u32;
+
+    fn contains(&self, coord: Coord) -> bool {
+        coord.x < self.width() && coord.y < self.height()
+    }
+
+    /// Creates a coordinate, checking that it lies on this board
+    fn coord(&self, x: u32, y: u32) -> Result<Coord, BoardError> {
+        let coord = Coord::new(x, y);
+        if self.contains(coord) {
+            Ok(coord)
+        } else {
+            Err(BoardError::OutOfBounds(coord))
+        }
+    }
+
+    fn current_player(&self) -> Player;
+
+    fn swap_player(&mut self);
+
+    fn cell(&self, coord: Coord) -> Result<Cell, BoardError>;
+
+    /// Determines which player controls the region containing the given square,
+    /// if any; the region map is only updated by `winner`
+    fn controller(&self, coord: Coord) -> Result<Option<Player>, BoardError>;
+
+    /// Determines whether the given squares are connected by an unobstructed
+    /// row, column, or diagonal
+    fn is_valid_move(&self, src: Coord, dst: Coord) -> bool;
+
+    fn has_valid_move(&self, player: Player) -> bool;
+
+    /// Moves one of the current player's amazons without shooting
+    fn move_amazon(&mut self, src: Coord, dst: Coord) -> Result<(), BoardError>;
+
+    /// Shoots an arrow from the amazon at `src`
+    fn shoot(&mut self, src: Coord, target: Coord) -> Result<(), BoardError>;
+
+    /// Puts an arrow on an empty square without playing a turn, for setting
+    /// up positions
+    fn place_arrow(&mut self, coord: Coord) -> Result<(), BoardError>;
+
+    /// Plays a full turn for the current player and passes the turn to the
+    /// opponent; the board is left untouched if any part of the turn is illegal
+    fn play(&mut self, src: Coord, dst: Coord, arrow: Coord) -> Result<(), BoardError> {
+        self.move_amazon(src, dst)?;
+        if let Err(e) = self.shoot(dst, arrow) {
+            // The path just taken is still clear since no arrow was placed
+            self.move_amazon(dst, src)?;
+            return Err(e);
+        }
+        self.swap_player();
+        Ok(())
+    }
+
+    /// Determines the outcome of the game, to be called after the turn has
+    /// passed to the next player
+    fn winner(&mut self) -> Result<Outcome, BoardError>;
+}
+
+/// Checks that starting positions lie on the board and do not overlap
+pub fn check_starting(
+    width: u32,
+    height: u32,
+    white_starting: &[(u32, u32)],
+    black_starting: &[(u32, u32)],
+) -> Result<(), BoardError> {
+    let mut seen = Vec::with_capacity(white_starting.len() + black_starting.len());
+    for &(x, y) in white_starting.iter().chain(black_starting) {
+        let coord = Coord::new(x, y);
+        if x >= width || y >= height {
+            return Err(BoardError::OutOfBounds(coord));
+        }
+        if seen.contains(&coord) {
+            return Err(BoardError::Overlapping(coord));
+        }
+        seen.push(coord);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+    use crate::movegen::{legal_moves, Move};
+    use crate::perft::perft;
+
+    /// Plain vector board, independent of the library, so that code written
+    /// against the trait is exercised with more than one implementation
+    #[derive(Clone)]
+    struct VecBoard {
+        width: u32,
+        height: u32,
+        cells: Vec<Cell>,
+        player: Player,
+    }
+
+    impl VecBoard {
+        fn at(&self, coord: Coord) -> usize {
+            (coord.y * self.width + coord.x) as usize
+        }
+    }
+
+    impl AmazonsEngine for VecBoard {
+        fn standard() -> Self {
+            let (white, black) = (
+                [(3, 0), (0, 3), (0, 6), (3, 9)],
+                [(6, 0), (9, 3), (9, 6), (6, 9)],
+            );
+            Self::new(10, 10, &white, &black).unwrap()
+        }
+
+        fn new(
+            width: u32,
+            height: u32,
+            white_starting: &[(u32, u32)],
+            black_starting: &[(u32, u32)],
+        ) -> Result<Self, BoardError> {
+            check_starting(width, height, white_starting, black_starting)?;
+            let mut board = VecBoard {
+                width,
+                height,
+                cells: vec![Cell::Empty; (width * height) as usize],
+                player: Player::White,
+            };
+            for (starting, player) in [
+                (white_starting, Player::White),
+                (black_starting, Player::Black),
+            ] {
+                for &(x, y) in starting {
+                    let i = board.at(Coord::new(x, y));
+                    board.cells[i] = Cell::Amazon(player);
+                }
+            }
+            Ok(board)
+        }
+
+        fn width(&self) -> u32 {
+            self.width
+        }
+
+        fn height(&self) -> u32 {
+            self.height
+        }
+
+        fn current_player(&self) -> Player {
+            self.player
+        }
+
+        fn swap_player(&mut self) {
+            self.player = self.player.opponent();
+        }
+
+        fn cell(&self, coord: Coord) -> Result<Cell, BoardError> {
+            let coord = self.coord(coord.x, coord.y)?;
+            Ok(self.cells[self.at(coord)])
+        }
+
+        fn controller(&self, coord: Coord) -> Result<Option<Player>, BoardError> {
+            self.coord(coord.x, coord.y).map(|_| None)
+        }
+
+        fn is_valid_move(&self, src: Coord, dst: Coord) -> bool {
+            if !self.contains(src) || !self.contains(dst) {
+                return false;
+            }
+            let (dx, dy) = (dst.x as i64 - src.x as i64, dst.y as i64 - src.y as i64);
+            if (dx, dy) == (0, 0) || (dx != 0 && dy != 0 && dx.abs() != dy.abs()) {
+                return false;
+            }
+            let steps = dx.abs().max(dy.abs());
+            (1..=steps).all(|i| {
+                let x = (src.x as i64 + i * dx.signum()) as u32;
+                let y = (src.y as i64 + i * dy.signum()) as u32;
+                self.cells[self.at(Coord::new(x, y))] == Cell::Empty
+            })
+        }
+
+        fn has_valid_move(&self, player: Player) -> bool {
+            (0..self.height).any(|y| {
+                (0..self.width).any(|x| {
+                    let src = Coord::new(x, y);
+                    self.cells[self.at(src)] == Cell::Amazon(player)
+                        && (y.saturating_sub(1)..=y + 1).any(|ny| {
+                            (x.saturating_sub(1)..=x + 1)
+                                .any(|nx| self.is_valid_move(src, Coord::new(nx, ny)))
+                        })
+                })
+            })
+        }
+
+        fn move_amazon(&mut self, src: Coord, dst: Coord) -> Result<(), BoardError> {
+            if self.cell(src)? != Cell::Amazon(self.player) {
+                return Err(BoardError::NotOwnPiece(src));
+            }
+            self.cell(dst)?;
+            if !self.is_valid_move(src, dst) {
+                return Err(BoardError::IllegalMove(src, dst));
+            }
+            let (from, to) = (self.at(src), self.at(dst));
+            self.cells.swap(from, to);
+            Ok(())
+        }
+
+        fn shoot(&mut self, src: Coord, target: Coord) -> Result<(), BoardError> {
+            self.cell(src)?;
+            self.cell(target)?;
+            if !self.is_valid_move(src, target) {
+                return Err(BoardError::IllegalShot(src, target));
+            }
+            let i = self.at(target);
+            self.cells[i] = Cell::Arrow;
+            Ok(())
+        }
+
+        fn place_arrow(&mut self, coord: Coord) -> Result<(), BoardError> {
+            if self.cell(coord)? != Cell::Empty {
+                return Err(BoardError::Overlapping(coord));
+            }
+            let i = self.at(coord);
+            self.cells[i] = Cell::Arrow;
+            Ok(())
+        }
+
+        // Territory is not counted; the game only ends once a player is stuck
+        fn winner(&mut self) -> Result<Outcome, BoardError> {
+            if self.has_valid_move(self.player) {
+                return Err(BoardError::GameNotOver);
+            }
+            Ok(Outcome {
+                winner: self.player.opponent(),
+                territory: None,
+            })
+        }
+    }
+
+    #[test]
+    fn move_generation_matches_the_library() {
+        let mut board = Board::new(6, 5, &[(0, 0), (5, 2)], &[(2, 4), (3, 1)]).unwrap();
+        let mut other = VecBoard::new(6, 5, &[(0, 0), (5, 2)], &[(2, 4), (3, 1)]).unwrap();
+        // Follow one line of play, picking a different move each turn
+        let mut turn = 0;
+        loop {
+            let moves: Vec<Move> = legal_moves(&board);
+            assert_eq!(moves, legal_moves(&other));
+            if moves.is_empty() {
+                break;
+            }
+            let mv = moves[turn * 7 % moves.len()];
+            mv.play(&mut board).unwrap();
+            mv.play(&mut other).unwrap();
+            turn += 1;
+        }
+        let player = other.current_player();
+        assert!(!board.has_valid_move(player) && !other.has_valid_move(player));
+        assert_eq!(other.winner().unwrap().winner, player.opponent());
+    }
+
+    #[test]
+    fn standard_perft() {
+        let board = VecBoard::standard();
+        assert_eq!(perft(&board, 1), 2176);
+        assert_eq!(perft(&board, 2), 4_307_152);
+        let small = VecBoard::new(4, 4, &[(0, 0)], &[(3, 3)]).unwrap();
+        let library = Board::new(4, 4, &[(0, 0)], &[(3, 3)]).unwrap();
+        assert_eq!(perft(&small, 3), perft(&library, 3));
+    }
+}
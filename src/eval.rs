@@ -0,0 +1,131 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Territory estimates for unfinished games. Every empty square is assigned
+//! to the player who can reach it in fewer moves, counting either queen moves
+//! or king moves from their nearest amazon.
+
+use crate::board::{Cell, Player};
+use crate::engine::AmazonsEngine;
+use crate::movegen::DIRECTIONS;
+use crate::position::Position;
+use std::collections::VecDeque;
+
+/// Distance of squares that no amazon of the player can reach
+pub const UNREACHABLE: u32 = u32::MAX;
+
+/// Minimum number of moves the player's amazons need to reach each square,
+/// in row-major order. With `king` set, amazons move one square at a time.
+pub fn distances(position: &Position, player: Player, king: bool) -> Vec<u32> {
+    let (width, height) = (position.width as i32, position.height as i32);
+    let mut dist = vec![UNREACHABLE; position.cells.len()];
+    let mut queue = VecDeque::new();
+    for (i, cell) in position.cells.iter().enumerate() {
+        if *cell == Cell::Amazon(player) {
+            dist[i] = 0;
+            queue.push_back(i);
+        }
+    }
+    while let Some(i) = queue.pop_front() {
+        let (x, y) = ((i as i32) % width, (i as i32) / width);
+        for (dx, dy) in DIRECTIONS {
+            let (mut nx, mut ny) = (x + dx, y + dy);
+            while nx >= 0 && ny >= 0 && nx < width && ny < height {
+                let j = (ny * width + nx) as usize;
+                if position.cells[j] != Cell::Empty {
+                    break;
+                }
+                if dist[j] == UNREACHABLE {
+                    dist[j] = dist[i] + 1;
+                    queue.push_back(j);
+                }
+                if king {
+                    break;
+                }
+                nx += dx;
+                ny += dy;
+            }
+        }
+    }
+    dist
+}
+
+/// Squares assigned to each player by comparing their distances
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Territory {
+    /// Owner of each square in row-major order; occupied squares and squares
+    /// both players reach equally fast have none
+    pub owners: Vec<Option<Player>>,
+    pub white: u32,
+    pub black: u32,
+}
+
+impl Territory {
+    pub fn new(position: &Position, king: bool) -> Self {
+        let white = distances(position, Player::White, king);
+        let black = distances(position, Player::Black, king);
+        let owners: Vec<_> = white
+            .iter()
+            .zip(&black)
+            .map(|(w, b)| match w.cmp(b) {
+                std::cmp::Ordering::Less => Some(Player::White),
+                std::cmp::Ordering::Greater => Some(Player::Black),
+                std::cmp::Ordering::Equal => None,
+            })
+            .zip(&position.cells)
+            .map(|(owner, cell)| owner.filter(|_| *cell == Cell::Empty))
+            .collect();
+        let count = |player| owners.iter().filter(|o| **o == Some(player)).count() as u32;
+        Territory {
+            white: count(Player::White),
+            black: count(Player::Black),
+            owners,
+        }
+    }
+
+    /// Territory of the given player minus that of the opponent
+    pub fn balance(&self, player: Player) -> i32 {
+        let (white, black) = (self.white as i32, self.black as i32);
+        match player {
+            Player::White => white - black,
+            Player::Black => black - white,
+        }
+    }
+}
+
+/// Static evaluation from the point of view of the player to move; queen
+/// distance territory counts double since it dominates in the opening and
+/// middle game, king distance territory refines it in the endgame
+pub fn evaluate<E: AmazonsEngine + ?Sized>(engine: &E) -> i32 {
+    let position = Position::from_engine(engine);
+    let player = position.to_move;
+    2 * Territory::new(&position, false).balance(player)
+        + Territory::new(&position, true).balance(player)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partitioned_territory() {
+        let position: Position = "W2x1B/3x2/3x2 w".parse().unwrap();
+        let queen = Territory::new(&position, false);
+        assert_eq!((queen.white, queen.black), (8, 5));
+        assert_eq!(distances(&position, Player::White, false)[2], 1);
+        assert_eq!(distances(&position, Player::White, true)[2], 2);
+        assert_eq!(distances(&position, Player::Black, false)[0], UNREACHABLE);
+        assert_eq!(queen.balance(Player::Black), -3);
+    }
+}
@@ -0,0 +1,763 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! A line-based engine protocol modelled on the Go Text Protocol. Each
+//! command is a line with an optional numeric id, the command name and its
+//! arguments; the response starts with `=` on success or `?` on failure,
+//! followed by the id if one was given, and ends with an empty line.
+//!
+//! Turns are written in move notation (`d1-d7/g7`) and positions as position
+//! strings. Besides the administrative commands of GTP, the engine supports
+//! `boardsize`, `clear_board`, `setup`, `play`, `genmove`, `undo`,
+//! `showboard`, `final_score` and `time_settings`.
+//!
+//! The other side of the protocol, `GtpClient`, runs an engine program as a
+//! child process so that it can take one side of a game.
+
+use crate::ai::{Ai, SearchControl};
+use crate::alphabeta::AlphaBeta;
+use crate::board::{Cell, Coord, Player};
+use crate::engine::AmazonsEngine;
+use crate::eval::Territory;
+use crate::history::History;
+use crate::movegen::Move;
+use crate::notation::{file_letter, MAX_SIZE};
+use crate::position::Position;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time an engine has to answer anything other than `genmove`, and how much
+/// longer than its time per move it may take before it is considered stuck
+pub const GRACE_TIME: Duration = Duration::from_secs(10);
+
+/// Time per move when the controller sets no time limit
+const DEFAULT_MOVE_TIME: Duration = Duration::from_secs(5);
+
+pub const COMMANDS: [&str; 16] = [
+    "protocol_version",
+    "name",
+    "version",
+    "known_command",
+    "list_commands",
+    "quit",
+    "boardsize",
+    "clear_board",
+    "setup",
+    "play",
+    "genmove",
+    "undo",
+    "showboard",
+    "final_score",
+    "time_settings",
+    "position",
+];
+
+/// Parses a colour argument such as `w`, `white`, `B` or `black`
+pub fn parse_player(s: &str) -> Option<Player> {
+    match s.to_ascii_lowercase().as_str() {
+        "w" | "white" => Some(Player::White),
+        "b" | "black" => Some(Player::Black),
+        _ => None,
+    }
+}
+
+pub fn player_name(player: Player) -> &'static str {
+    match player {
+        Player::White => "white",
+        Player::Black => "black",
+    }
+}
+
+/// Formats a response to a command with the given id
+pub fn response(id: Option<u32>, result: Result<String, String>) -> String {
+    let id = id.map_or_else(String::new, |id| id.to_string());
+    match result {
+        Ok(text) if text.is_empty() => format!("={}\n\n", id),
+        Ok(text) => format!("={} {}\n\n", id, text),
+        Err(text) => format!("?{} {}\n\n", id, text),
+    }
+}
+
+/// Plain text diagram of a position, rank 1 first as on screen
+pub fn diagram<E: AmazonsEngine + ?Sized>(engine: &E) -> String {
+    let files: String = (0..engine.width()).map(file_letter).collect();
+    let mut text = format!("   {}\n", files);
+    for y in 0..engine.height() {
+        let row: String = (0..engine.width())
+            .map(|x| match engine.cell(Coord::new(x, y)) {
+                Ok(Cell::Amazon(Player::White)) => 'W',
+                Ok(Cell::Amazon(Player::Black)) => 'B',
+                Ok(Cell::Arrow) => 'x',
+                _ => '.',
+            })
+            .collect();
+        text.push_str(&format!("{:>2} {}\n", y + 1, row));
+    }
+    text.push_str(&format!("{} to move", player_name(engine.current_player())));
+    text
+}
+
+/// State of an engine speaking the protocol
+pub struct GtpEngine<E> {
+    history: History<E>,
+    move_time: Duration,
+    quit: bool,
+}
+
+impl<E: AmazonsEngine + Clone> Default for GtpEngine<E> {
+    fn default() -> Self {
+        GtpEngine {
+            history: History::new(E::standard()),
+            move_time: DEFAULT_MOVE_TIME,
+            quit: false,
+        }
+    }
+}
+
+impl<E: AmazonsEngine + Clone> GtpEngine<E> {
+    /// Whether `quit` has been received
+    pub fn finished(&self) -> bool {
+        self.quit
+    }
+
+    pub fn board(&self) -> &E {
+        self.history
+            .current()
+            .expect("history always holds the starting position")
+    }
+
+    /// Runs one line of input and returns the response, or `None` for
+    /// empty lines and comments
+    pub fn execute(&mut self, line: &str) -> Option<String> {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut words = line.split_whitespace().peekable();
+        let id = words.peek().and_then(|word| word.parse::<u32>().ok());
+        if id.is_some() {
+            words.next();
+        }
+        let command = words.next()?;
+        let args: Vec<&str> = words.collect();
+        Some(response(id, self.run(command, &args)))
+    }
+
+    fn run(&mut self, command: &str, args: &[&str]) -> Result<String, String> {
+        match command {
+            "protocol_version" => Ok("2".to_string()),
+            "name" => Ok("amazons".to_string()),
+            "version" => Ok(env!("CARGO_PKG_VERSION").to_string()),
+            "known_command" => {
+                let known = args.first().is_some_and(|c| COMMANDS.contains(c));
+                Ok(known.to_string())
+            }
+            "list_commands" => Ok(COMMANDS.join("\n")),
+            "quit" => {
+                self.quit = true;
+                Ok(String::new())
+            }
+            "boardsize" => self.boardsize(args),
+            "clear_board" => {
+                let start = self.board_at_start();
+                self.history = History::new(start);
+                Ok(String::new())
+            }
+            "setup" => {
+                let position: Position = args.join(" ").parse().map_err(|e| format!("{}", e))?;
+                let board = position.to_engine().map_err(|e| e.to_string())?;
+                self.history = History::new(board);
+                Ok(String::new())
+            }
+            "position" => Ok(Position::from_engine(self.board()).to_string()),
+            "play" => self.play(args),
+            "genmove" => self.genmove(args),
+            "undo" => match self.history.undo() {
+                Some(_) => Ok(String::new()),
+                None => Err("cannot undo".to_string()),
+            },
+            "showboard" => Ok(format!("\n{}", diagram(self.board()))),
+            "final_score" => Ok(self.final_score()),
+            "time_settings" => self.time_settings(args),
+            _ => Err("unknown command".to_string()),
+        }
+    }
+
+    // The standard opening on a 10x10 board, otherwise an empty board to be
+    // filled with `setup`
+    fn board_at_start(&self) -> E {
+        let (width, height) = (self.board().width(), self.board().height());
+        if (width, height) == (10, 10) {
+            return E::standard();
+        }
+        E::new(width, height, &[], &[]).unwrap_or_else(|_| E::standard())
+    }
+
+    fn boardsize(&mut self, args: &[&str]) -> Result<String, String> {
+        let size = |arg: Option<&&str>| -> Result<u32, String> {
+            match arg.map(|s| s.parse::<u32>()) {
+                Some(Ok(n)) if (1..=MAX_SIZE).contains(&n) => Ok(n),
+                _ => Err("unacceptable size".to_string()),
+            }
+        };
+        let width = size(args.first())?;
+        let height = if args.len() > 1 {
+            size(args.get(1))?
+        } else {
+            width
+        };
+        let board = if (width, height) == (10, 10) {
+            E::standard()
+        } else {
+            E::new(width, height, &[], &[]).map_err(|e| e.to_string())?
+        };
+        self.history = History::new(board);
+        Ok(String::new())
+    }
+
+    // Checks an optional colour argument against the player to move
+    fn check_colour(&self, colour: Option<&&str>) -> Result<(), String> {
+        let to_move = self.board().current_player();
+        match colour.map(|c| parse_player(c)) {
+            None => Ok(()),
+            Some(None) => Err("invalid color".to_string()),
+            Some(Some(player)) if player == to_move => Ok(()),
+            Some(Some(_)) => Err(format!("{} is to move", player_name(to_move))),
+        }
+    }
+
+    fn play(&mut self, args: &[&str]) -> Result<String, String> {
+        let (colour, mv) = match args {
+            [mv] => (None, mv),
+            [colour, mv] => (Some(colour), mv),
+            _ => return Err("expected play [color] <move>".to_string()),
+        };
+        self.check_colour(colour)?;
+        let mv: Move = mv.parse().map_err(|e| format!("{}", e))?;
+        self.push(mv)?;
+        Ok(String::new())
+    }
+
+    // Play goes on after the board is partitioned until the side to move is stuck
+    fn push(&mut self, mv: Move) -> Result<(), String> {
+        let mut board = self.board().clone();
+        if !board.has_valid_move(board.current_player()) {
+            return Err("the game is over".to_string());
+        }
+        mv.play(&mut board).map_err(|e| e.to_string())?;
+        self.history.push(mv, board);
+        Ok(())
+    }
+
+    fn genmove(&mut self, args: &[&str]) -> Result<String, String> {
+        self.check_colour(args.first())?;
+        let board = self.board();
+        if !board.has_valid_move(board.current_player()) {
+            return Ok("resign".to_string());
+        }
+        let mut ai = AlphaBeta::new(self.move_time);
+        match ai.choose_move(self.board(), &SearchControl::default()) {
+            Some(mv) => {
+                self.push(mv)?;
+                Ok(mv.to_string())
+            }
+            None => Ok("resign".to_string()),
+        }
+    }
+
+    // The result if the game is over, otherwise an estimate from territory
+    fn final_score(&self) -> String {
+        let mut board = self.board().clone();
+        let (winner, margin) = match board.winner() {
+            Ok(outcome) => {
+                let margin = outcome
+                    .territory
+                    .map_or(0, |(w, b)| (w as i32 - b as i32).abs());
+                (Some(outcome.winner), margin)
+            }
+            Err(_) => {
+                let territory = Territory::new(&Position::from_engine(&board), false);
+                let balance = territory.balance(Player::White);
+                let winner = match balance {
+                    0 => None,
+                    b if b > 0 => Some(Player::White),
+                    _ => Some(Player::Black),
+                };
+                (winner, balance.abs())
+            }
+        };
+        match winner {
+            Some(Player::White) => format!("W+{}", margin),
+            Some(Player::Black) => format!("B+{}", margin),
+            None => "0".to_string(),
+        }
+    }
+
+    // Uses the byo-yomi period per stone as the time for each move, or a
+    // thirtieth of the main time without byo-yomi. Byo-yomi without stones, or
+    // no time at all, means there is no limit.
+    fn time_settings(&mut self, args: &[&str]) -> Result<String, String> {
+        let numbers: Result<Vec<f64>, _> = args.iter().map(|a| a.parse::<f64>()).collect();
+        let seconds = match numbers.as_deref() {
+            Ok([_, byo_yomi, stones]) if *stones > 0. => byo_yomi / stones,
+            Ok([main, byo_yomi, stones])
+                if *stones == 0. && (*byo_yomi > 0. || (*main == 0. && *byo_yomi == 0.)) =>
+            {
+                DEFAULT_MOVE_TIME.as_secs_f64()
+            }
+            Ok([main, _, _]) => main / 30.,
+            _ => return Err("expected time_settings <main> <byo-yomi> <stones>".to_string()),
+        };
+        if !seconds.is_finite() || seconds <= 0. {
+            return Err("time must be positive".to_string());
+        }
+        self.move_time = Duration::from_secs_f64(seconds);
+        Ok(String::new())
+    }
+}
+
+/// Reasons for which an external engine could not be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientError {
+    Spawn(String),
+    /// The engine closed its output, usually because the process ended
+    Exited,
+    Timeout(String),
+    Rejected {
+        command: String,
+        reason: String,
+    },
+    InvalidResponse(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Spawn(e) => write!(f, "could not start the engine: {}", e),
+            ClientError::Exited => write!(f, "the engine exited"),
+            ClientError::Timeout(command) => {
+                write!(f, "the engine did not answer '{}' in time", command)
+            }
+            ClientError::Rejected { command, reason } => {
+                write!(f, "the engine rejected '{}': {}", command, reason)
+            }
+            ClientError::InvalidResponse(text) => {
+                write!(f, "unexpected response from the engine: '{}'", text)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+struct Pending {
+    command: String,
+    timeout: Duration,
+    /// Abandoned commands are still answered but the answer is dropped
+    wanted: bool,
+    /// The engine may reject the command without this being an error
+    optional: bool,
+}
+
+/// An engine program speaking the protocol on its standard input and output.
+/// Commands are sent without waiting, and their responses are collected in
+/// order with `poll`, so a slow or broken engine never blocks the caller.
+/// Dropping the client kills the process.
+pub struct GtpClient {
+    child: Child,
+    stdin: ChildStdin,
+    responses: Receiver<String>,
+    pending: VecDeque<Pending>,
+    // When the oldest pending command reached the engine's attention
+    since: Instant,
+}
+
+impl GtpClient {
+    pub fn spawn(program: &str, args: &[&str]) -> Result<Self, ClientError> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| ClientError::Spawn(e.to_string()))?;
+        let (stdin, stdout) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => (stdin, stdout),
+            _ => return Err(ClientError::Spawn("no pipes to the process".to_string())),
+        };
+        let (sender, responses) = mpsc::channel();
+        // Collects the lines of each response up to the empty line ending it
+        thread::spawn(move || {
+            let mut response = String::new();
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                let line = line.trim_end();
+                if !line.is_empty() {
+                    if !response.is_empty() {
+                        response.push('\n');
+                    }
+                    response.push_str(line);
+                } else if !response.is_empty() && sender.send(response.split_off(0)).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(GtpClient {
+            child,
+            stdin,
+            responses,
+            pending: VecDeque::new(),
+            since: Instant::now(),
+        })
+    }
+
+    /// Sends a command; its response is one of those returned by `poll`
+    pub fn send(&mut self, command: &str, timeout: Duration) -> Result<(), ClientError> {
+        self.queue(command, timeout, false)
+    }
+
+    /// Sends a command that not every engine supports; if the engine rejects
+    /// it, `poll` skips the response instead of returning an error
+    pub fn send_optional(&mut self, command: &str, timeout: Duration) -> Result<(), ClientError> {
+        self.queue(command, timeout, true)
+    }
+
+    fn queue(
+        &mut self,
+        command: &str,
+        timeout: Duration,
+        optional: bool,
+    ) -> Result<(), ClientError> {
+        writeln!(self.stdin, "{}", command)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|_| ClientError::Exited)?;
+        if self.pending.is_empty() {
+            self.since = Instant::now();
+        }
+        self.pending.push_back(Pending {
+            command: command.to_string(),
+            timeout,
+            wanted: true,
+            optional,
+        });
+        Ok(())
+    }
+
+    /// Whether responses are still expected for commands that were not
+    /// abandoned
+    pub fn is_waiting(&self) -> bool {
+        self.pending.iter().any(|p| p.wanted)
+    }
+
+    /// Ignores the responses to all commands sent so far
+    pub fn abandon(&mut self) {
+        for pending in &mut self.pending {
+            pending.wanted = false;
+        }
+    }
+
+    /// The text of the next response if it has arrived, or an error if the
+    /// engine rejected the command, exited or took too long
+    pub fn poll(&mut self) -> Option<Result<String, ClientError>> {
+        while let Some(pending) = self.pending.front() {
+            let text = match self.responses.try_recv() {
+                Ok(text) => text,
+                Err(TryRecvError::Empty) if self.since.elapsed() > pending.timeout => {
+                    return Some(Err(ClientError::Timeout(pending.command.clone())));
+                }
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => return Some(Err(ClientError::Exited)),
+            };
+            let pending = self.pending.pop_front()?;
+            self.since = Instant::now();
+            match parse_response(&pending.command, &text) {
+                Err(ClientError::Rejected { .. }) if pending.optional => (),
+                response if pending.wanted => return Some(response),
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// Sends a command and waits for its response
+    pub fn command(&mut self, command: &str, timeout: Duration) -> Result<String, ClientError> {
+        self.send(command, timeout)?;
+        loop {
+            match self.poll() {
+                Some(result) => return result,
+                None => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+    }
+}
+
+impl Drop for GtpClient {
+    fn drop(&mut self) {
+        // The engine may already have exited
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Strips the status character and id from a response
+fn parse_response(command: &str, text: &str) -> Result<String, ClientError> {
+    let (success, rest) = match text.chars().next() {
+        Some('=') => (true, &text[1..]),
+        Some('?') => (false, &text[1..]),
+        _ => return Err(ClientError::InvalidResponse(text.to_string())),
+    };
+    let rest = rest.trim_start_matches(|c: char| c.is_ascii_digit()).trim();
+    if success {
+        Ok(rest.to_string())
+    } else {
+        Err(ClientError::Rejected {
+            command: command.to_string(),
+            reason: rest.to_string(),
+        })
+    }
+}
+
+/// An external engine playing one side of a game. Before each of its turns
+/// the engine is sent the turns played since it last moved, or the whole
+/// game if it was undone or restarted in the meantime.
+pub struct ExternalEngine {
+    client: GtpClient,
+    // Starting position and turns the engine has been told about
+    start: Option<String>,
+    told: Vec<Move>,
+}
+
+impl ExternalEngine {
+    pub fn spawn(program: &str) -> Result<Self, ClientError> {
+        Ok(ExternalEngine {
+            client: GtpClient::spawn(program, &[])?,
+            start: None,
+            told: vec![],
+        })
+    }
+
+    /// Whether the engine is still working on a turn
+    pub fn is_thinking(&self) -> bool {
+        self.client.is_waiting()
+    }
+
+    /// Asks the engine for a turn in the position reached by playing `moves`
+    /// from `start`, with `move_time` to think
+    pub fn request_move<E: AmazonsEngine>(
+        &mut self,
+        start: &E,
+        moves: &[Move],
+        player: Player,
+        move_time: Duration,
+    ) -> Result<(), ClientError> {
+        let position = Position::from_engine(start).to_string();
+        if self.start.as_ref() != Some(&position) || !moves.starts_with(&self.told) {
+            self.client
+                .send(&format!("setup {}", position), GRACE_TIME)?;
+            self.start = Some(position);
+            self.told.clear();
+        }
+        for mv in &moves[self.told.len()..] {
+            self.client.send(&format!("play {}", mv), GRACE_TIME)?;
+            self.told.push(*mv);
+        }
+        // The protocol only has whole seconds; engines that do not support
+        // the command keep their own time control
+        let seconds = move_time.as_secs_f64().ceil().max(1.) as u64;
+        self.client
+            .send_optional(&format!("time_settings 0 {} 1", seconds), GRACE_TIME)?;
+        self.client.send(
+            &format!("genmove {}", player_name(player)),
+            move_time + GRACE_TIME,
+        )
+    }
+
+    /// The engine's turn once it has answered, or `None` inside if it resigned
+    pub fn poll_move(&mut self) -> Option<Result<Option<Move>, ClientError>> {
+        while let Some(result) = self.client.poll() {
+            let text = match result {
+                Ok(text) => text,
+                Err(e) => {
+                    self.start = None;
+                    return Some(Err(e));
+                }
+            };
+            // Only the answer to `genmove` carries anything
+            if self.client.is_waiting() {
+                continue;
+            }
+            if text.eq_ignore_ascii_case("resign") {
+                return Some(Ok(None));
+            }
+            return Some(match text.parse::<Move>() {
+                Ok(mv) => {
+                    self.told.push(mv);
+                    Ok(Some(mv))
+                }
+                Err(_) => Err(ClientError::InvalidResponse(text)),
+            });
+        }
+        None
+    }
+
+    /// Drops the turn being worked on; the engine is sent the whole game
+    /// again with the next request
+    pub fn abandon(&mut self) {
+        if self.client.is_waiting() {
+            self.client.abandon();
+            self.start = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+
+    #[test]
+    fn session() {
+        let mut engine = GtpEngine::<Board>::default();
+        assert_eq!(
+            engine.execute("1 protocol_version"),
+            Some("=1 2\n\n".to_string())
+        );
+        assert_eq!(engine.execute("  # comment"), None);
+        assert_eq!(
+            engine.execute("play white d1-d7/g7"),
+            Some("=\n\n".to_string())
+        );
+        assert_eq!(
+            engine.execute("play white g10-g8/e6"),
+            Some("? black is to move\n\n".to_string())
+        );
+        assert_eq!(
+            engine.execute("play d7-d1/g7"),
+            Some("? no amazon of the player to move at d7\n\n".to_string())
+        );
+        assert_eq!(engine.execute("undo"), Some("=\n\n".to_string()));
+        assert_eq!(
+            engine.execute("position").unwrap(),
+            "= 3W2B3/10/10/W8B/10/10/W8B/10/10/3W2B3 w\n\n"
+        );
+        assert_eq!(
+            engine.execute("frobnicate"),
+            Some("? unknown command\n\n".to_string())
+        );
+        engine.execute("quit");
+        assert!(engine.finished());
+    }
+
+    #[test]
+    fn genmove_and_score() {
+        let mut engine = GtpEngine::<Board>::default();
+        assert_eq!(engine.execute("setup W2B w"), Some("=\n\n".to_string()));
+        assert_eq!(
+            engine.execute("time_settings 0 0 0"),
+            Some("=\n\n".to_string())
+        );
+        assert_eq!(engine.move_time, DEFAULT_MOVE_TIME);
+        assert_eq!(
+            engine.execute("time_settings 0 1 10"),
+            Some("=\n\n".to_string())
+        );
+        assert_eq!(engine.move_time, Duration::from_millis(100));
+        assert_eq!(
+            engine.execute("showboard").unwrap(),
+            "= \n   abcd\n 1 W..B\nwhite to move\n\n"
+        );
+        assert!(engine.execute("genmove w").unwrap().starts_with("= a1-"));
+        assert_eq!(
+            engine.execute("genmove b"),
+            Some("= resign\n\n".to_string())
+        );
+        assert_eq!(engine.execute("final_score"), Some("= W+0\n\n".to_string()));
+    }
+
+    #[test]
+    fn play_continues_after_partition() {
+        let mut engine = GtpEngine::<Board>::default();
+        engine.execute("setup W1xB2 b");
+        assert_eq!(engine.execute("final_score"), Some("= B+1\n\n".to_string()));
+        assert!(engine.execute("genmove b").unwrap().starts_with("= d1-"));
+
+        engine.execute("setup W1xB2 w");
+        assert_eq!(
+            engine.execute("genmove w"),
+            Some("= a1-b1/a1\n\n".to_string())
+        );
+        assert_eq!(engine.execute("play b d1-e1/f1"), Some("=\n\n".to_string()));
+        assert_eq!(
+            engine.execute("play w b1-a1/b1"),
+            Some("? the game is over\n\n".to_string())
+        );
+        assert_eq!(
+            engine.execute("genmove w"),
+            Some("= resign\n\n".to_string())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn client_errors() {
+        let script = "while read line; do echo '= a1-b1/c1'; echo; done";
+        let board = Board::new(4, 1, &[(0, 0)], &[(3, 0)]).unwrap();
+        let second = Duration::from_secs(1);
+        let turn_in = |program: &str, args: &[&str], move_time: Duration| {
+            let mut engine = ExternalEngine {
+                client: GtpClient::spawn(program, args)?,
+                start: None,
+                told: vec![],
+            };
+            engine.request_move(&board, &[], Player::White, move_time)?;
+            loop {
+                match engine.poll_move() {
+                    Some(result) => return result,
+                    None => thread::sleep(Duration::from_millis(5)),
+                }
+            }
+        };
+        let turn = |program: &str, args: &[&str]| turn_in(program, args, second);
+        let mv = Move::new(Coord::new(0, 0), Coord::new(1, 0), Coord::new(2, 0));
+        assert_eq!(turn("sh", &["-c", script]), Ok(Some(mv)));
+        // Engines without time control still play, and are given whole seconds
+        let strict = "while read command args; do case $command in \
+                      time_settings) t=$args; echo '? unknown command';; \
+                      genmove) [ \"$t\" = '0 2 1' ] && echo '= a1-b1/c1' || echo \"? $t\";; \
+                      *) echo '=';; esac; echo; done";
+        let move_time = Duration::from_millis(1500);
+        assert_eq!(turn_in("sh", &["-c", strict], move_time), Ok(Some(mv)));
+        assert_eq!(turn("true", &[]), Err(ClientError::Exited));
+        assert!(matches!(
+            turn("no-such-engine", &[]),
+            Err(ClientError::Spawn(_))
+        ));
+
+        let mut client = GtpClient::spawn("sh", &["-c", script]).unwrap();
+        assert_eq!(client.command("genmove w", second).unwrap(), "a1-b1/c1");
+        let mut client = GtpClient::spawn("sleep", &["10"]).unwrap();
+        let timeout = client.command("genmove w", Duration::from_millis(50));
+        assert_eq!(timeout, Err(ClientError::Timeout("genmove w".to_string())));
+        let mut client = GtpClient::spawn("sh", &["-c", "read line; echo '? no'; echo"]).unwrap();
+        assert!(matches!(
+            client.command("play a1-b1/c1", second),
+            Err(ClientError::Rejected { .. })
+        ));
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::movegen::Move;
+
+/// Record of the turns played in a game along with the position after each
+/// of them, so that turns can be undone and redone without replaying the game
+#[derive(Debug, Clone, Default)]
+pub struct History<E> {
+    positions: Vec<E>,
+    moves: Vec<Move>,
+    cursor: usize,
+}
+
+impl<E> History<E> {
+    pub fn new(start: E) -> Self {
+        History {
+            positions: vec![start],
+            moves: vec![],
+            cursor: 0,
+        }
+    }
+
+    /// Records a turn played from the current position; any undone turns
+    /// are discarded
+    pub fn push(&mut self, mv: Move, position: E) {
+        self.positions.truncate(self.cursor + 1);
+        self.moves.truncate(self.cursor);
+        self.positions.push(position);
+        self.moves.push(mv);
+        self.cursor += 1;
+    }
+
+    /// Steps back one turn and returns the position before it
+    pub fn undo(&mut self) -> Option<&E> {
+        if !self.can_undo() {
+            return None;
+        }
+        self.cursor -= 1;
+        self.positions.get(self.cursor)
+    }
+
+    /// Replays the last undone turn and returns the position after it
+    pub fn redo(&mut self) -> Option<&E> {
+        if !self.can_redo() {
+            return None;
+        }
+        self.cursor += 1;
+        self.positions.get(self.cursor)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.cursor > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.cursor < self.moves.len()
+    }
+
+    /// Number of turns leading to the current position
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Every recorded turn, including those that have been undone
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    /// Position after the given number of turns
+    pub fn position(&self, turns: usize) -> Option<&E> {
+        self.positions.get(turns)
+    }
+
+    pub fn current(&self) -> Option<&E> {
+        self.positions.get(self.cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Coord;
+
+    #[test]
+    fn push_discards_undone_turns() {
+        let mv = |x| Move::new(Coord::new(x, 0), Coord::new(x, 1), Coord::new(x, 2));
+        let mut history = History::new(0);
+        history.push(mv(0), 1);
+        history.push(mv(1), 2);
+        assert_eq!(history.undo(), Some(&1));
+        assert_eq!(history.undo(), Some(&0));
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.redo(), Some(&1));
+        assert!(history.can_redo());
+        history.push(mv(2), 3);
+        assert!(!history.can_redo());
+        assert_eq!(history.moves(), &[mv(0), mv(2)]);
+        assert_eq!(history.current(), Some(&3));
+    }
+}
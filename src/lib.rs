@@ -0,0 +1,33 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+pub mod ai;
+pub mod alphabeta;
+pub mod board;
+pub mod boardstate;
+pub mod engine;
+pub mod eval;
+pub mod gtp;
+pub mod history;
+pub mod mcts;
+pub mod movegen;
+#[cfg(feature = "pure-rust")]
+pub mod native;
+pub mod net;
+pub mod notation;
+pub mod perft;
+pub mod position;
+pub mod record;
+pub mod server;
+pub mod sgf;
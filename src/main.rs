@@ -2,13 +2,13 @@
 // https://github.com/emilk/eframe_template
 
 mod app;
-mod boardstate;
 mod sprites;
 
+use amazons::board::Board;
 use app::AmazonsGame;
 
 fn main() {
-    let app = AmazonsGame::default();
+    let app = AmazonsGame::<Board>::default();
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(Box::new(app), native_options);
 }
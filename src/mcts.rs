@@ -0,0 +1,323 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Monte Carlo tree search with the UCT selection rule. Each iteration walks
+//! down the tree, adds one turn, and finishes the game with random turns. A
+//! playout ends as soon as the rules engine reports a winner, which includes
+//! the board being partitioned into regions that each belong to one player,
+//! so most playouts stop well before the board is full.
+
+use crate::ai::{Ai, SearchControl};
+use crate::board::Player;
+use crate::engine::AmazonsEngine;
+use crate::movegen::{arrow_targets, destinations, legal_moves, own_amazons, Move};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// When to stop searching
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Playouts(u64),
+    Time(Duration),
+}
+
+struct Node {
+    mv: Option<Move>,
+    children: Vec<usize>,
+    /// Turns not yet added to the tree; generated on the first visit
+    untried: Option<Vec<Move>>,
+    visits: u32,
+    /// Playouts won by the player who made `mv`
+    wins: u32,
+    mover: Player,
+}
+
+impl Node {
+    fn new(mv: Option<Move>, mover: Player) -> Self {
+        Node {
+            mv,
+            children: vec![],
+            untried: None,
+            visits: 0,
+            wins: 0,
+            mover,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mcts {
+    pub limit: Limit,
+    /// Weight of the exploration term in the UCT formula
+    pub exploration: f64,
+    playouts: u64,
+    rng: u64,
+}
+
+impl Mcts {
+    pub fn new(limit: Limit, exploration: f64) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_nanos() as u64);
+        Mcts {
+            limit,
+            exploration,
+            playouts: 0,
+            // The generator state must not be zero
+            rng: seed | 1,
+        }
+    }
+
+    /// Number of playouts run by the last search
+    pub fn playouts(&self) -> u64 {
+        self.playouts
+    }
+
+    // xorshift64
+    fn random(&mut self, n: usize) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng % n as u64) as usize
+    }
+
+    /// Picks a random legal turn without listing all of them
+    fn random_move<E: AmazonsEngine + ?Sized>(&mut self, engine: &E) -> Option<Move> {
+        let amazons = own_amazons(engine);
+        let offset = self.random(amazons.len().max(1));
+        for i in 0..amazons.len() {
+            let from = amazons[(offset + i) % amazons.len()];
+            let targets = destinations(engine, from);
+            if targets.is_empty() {
+                continue;
+            }
+            let to = targets[self.random(targets.len())];
+            // The vacated square can always be shot at
+            let arrows = arrow_targets(engine, from, to);
+            let arrow = arrows[self.random(arrows.len())];
+            return Some(Move::new(from, to, arrow));
+        }
+        None
+    }
+
+    fn playout<E: AmazonsEngine>(&mut self, engine: &mut E) -> Player {
+        loop {
+            if let Ok(outcome) = engine.winner() {
+                return outcome.winner;
+            }
+            match self.random_move(engine) {
+                Some(mv) => mv
+                    .play(engine)
+                    .expect("generated move rejected by the rules engine"),
+                None => return engine.current_player().opponent(),
+            }
+        }
+    }
+
+    fn select(&self, nodes: &[Node], parent: usize) -> usize {
+        let log_visits = (nodes[parent].visits.max(1) as f64).ln();
+        let uct = |child: &Node| {
+            let visits = child.visits.max(1) as f64;
+            child.wins as f64 / visits + self.exploration * (log_visits / visits).sqrt()
+        };
+        let mut best = nodes[parent].children[0];
+        for &child in &nodes[parent].children {
+            if uct(&nodes[child]) > uct(&nodes[best]) {
+                best = child;
+            }
+        }
+        best
+    }
+
+    fn iterate<E: AmazonsEngine + Clone>(&mut self, root: &E, nodes: &mut Vec<Node>) {
+        let mut state = root.clone();
+        let mut path = vec![0];
+        let mut node = 0;
+        loop {
+            if nodes[node].untried.is_none() {
+                nodes[node].untried = Some(legal_moves(&state));
+            }
+            let untried = nodes[node].untried.as_ref().map_or(0, Vec::len);
+            if untried > 0 || nodes[node].children.is_empty() {
+                break;
+            }
+            node = self.select(nodes, node);
+            if let Some(mv) = nodes[node].mv {
+                mv.play(&mut state)
+                    .expect("generated move rejected by the rules engine");
+            }
+            path.push(node);
+        }
+        let untried = nodes[node].untried.as_ref().map_or(0, Vec::len);
+        if untried > 0 {
+            let i = self.random(untried);
+            if let Some(mv) = nodes[node]
+                .untried
+                .as_mut()
+                .map(|moves| moves.swap_remove(i))
+            {
+                let mover = state.current_player();
+                mv.play(&mut state)
+                    .expect("generated move rejected by the rules engine");
+                nodes.push(Node::new(Some(mv), mover));
+                let child = nodes.len() - 1;
+                nodes[node].children.push(child);
+                path.push(child);
+            }
+        }
+        let winner = self.playout(&mut state);
+        self.playouts += 1;
+        for &i in &path {
+            nodes[i].visits += 1;
+            if nodes[i].mover == winner {
+                nodes[i].wins += 1;
+            }
+        }
+    }
+
+    // Grows a tree from the given position until the limit is reached; the
+    // root is the first node
+    fn search<E: AmazonsEngine + Clone>(
+        &mut self,
+        engine: &E,
+        control: &SearchControl,
+    ) -> Vec<Node> {
+        let start = Instant::now();
+        let mut nodes = vec![Node::new(None, engine.current_player().opponent())];
+        loop {
+            let done = match self.limit {
+                Limit::Playouts(n) => self.playouts >= n,
+                Limit::Time(budget) => start.elapsed() >= budget,
+            };
+            if done || control.is_stopped() {
+                break;
+            }
+            self.iterate(engine, &mut nodes);
+            control.add_nodes(1);
+        }
+        nodes
+    }
+}
+
+impl<E: AmazonsEngine + Clone> Ai<E> for Mcts {
+    fn choose_move(&mut self, engine: &E, control: &SearchControl) -> Option<Move> {
+        self.playouts = 0;
+        let moves = legal_moves(engine);
+        if moves.len() <= 1 {
+            return moves.first().copied();
+        }
+        let nodes = self.search(engine, control);
+        // The most visited turn is the most reliable choice
+        nodes[0]
+            .children
+            .iter()
+            .max_by_key(|&&child| nodes[child].visits)
+            .and_then(|&child| nodes[child].mv)
+            .or_else(|| moves.first().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{Board, Cell};
+    use crate::position::Position;
+
+    fn seeded(limit: Limit, exploration: f64) -> Mcts {
+        let mut ai = Mcts::new(limit, exploration);
+        ai.rng = 0x2545_f491_4f6c_dd1d;
+        ai
+    }
+
+    fn empty_squares(board: &Board) -> usize {
+        Position::from_engine(board).squares(Cell::Empty).len()
+    }
+
+    #[test]
+    fn playouts_stop_at_partition() {
+        // A wall of arrows already separates the players
+        let mut walled: Board = "W1x3/2x2B/2x3/1Wx3 w"
+            .parse::<Position>()
+            .unwrap()
+            .to_engine()
+            .unwrap();
+        let mut ai = seeded(Limit::Playouts(1), 1.4);
+        assert_eq!(ai.playout(&mut walled), Player::Black);
+        assert_eq!(empty_squares(&walled), 17);
+
+        // From an open board, playouts end once the board splits, long
+        // before every square is used up
+        let start = Board::new(8, 8, &[(0, 0), (7, 0)], &[(0, 7), (7, 7)]).unwrap();
+        let left: usize = (0..20)
+            .map(|_| {
+                let mut board = start.clone();
+                ai.playout(&mut board);
+                assert!(board.winner().is_ok());
+                empty_squares(&board)
+            })
+            .sum();
+        assert!(left > 20 * 5, "only {} squares left over 20 playouts", left);
+    }
+
+    #[test]
+    fn limits() {
+        let board = Board::new(5, 5, &[(0, 0)], &[(4, 4)]).unwrap();
+        let control = SearchControl::default();
+        let mut ai = seeded(Limit::Playouts(150), 1.4);
+        assert!(ai.choose_move(&board, &control).is_some());
+        assert_eq!(ai.playouts(), 150);
+        assert_eq!(control.nodes(), 150);
+
+        let mut ai = seeded(Limit::Time(Duration::from_millis(100)), 1.4);
+        let start = Instant::now();
+        assert!(ai.choose_move(&board, &SearchControl::default()).is_some());
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(100) && elapsed < Duration::from_secs(2));
+        assert!(ai.playouts() > 0);
+
+        // Without any playouts some legal turn is still returned
+        let mut ai = seeded(Limit::Time(Duration::ZERO), 1.4);
+        let mv = ai.choose_move(&board, &SearchControl::default()).unwrap();
+        assert_eq!(ai.playouts(), 0);
+        assert!(mv.play(&mut board.clone()).is_ok());
+    }
+
+    #[test]
+    fn exploration_spreads_visits() {
+        let board = Board::new(5, 5, &[(0, 0)], &[(4, 4)]).unwrap();
+        let most_visited = |exploration| {
+            let mut ai = seeded(Limit::Playouts(2000), exploration);
+            let nodes = ai.search(&board, &SearchControl::default());
+            let root = &nodes[0];
+            assert_eq!(root.visits, 2000);
+            root.children
+                .iter()
+                .map(|&c| nodes[c].visits)
+                .max()
+                .unwrap()
+        };
+        let greedy = most_visited(0.0);
+        let wide = most_visited(100.0);
+        assert!(greedy > 4 * wide, "greedy {} vs wide {}", greedy, wide);
+    }
+
+    #[test]
+    fn finds_winning_turn() {
+        let board = Board::new(4, 1, &[(0, 0)], &[(3, 0)]).unwrap();
+        let mut ai = seeded(Limit::Playouts(200), 1.4);
+        let mv = ai.choose_move(&board, &SearchControl::default()).unwrap();
+        let mut after = board.clone();
+        mv.play(&mut after).unwrap();
+        assert!(!after.has_valid_move(after.current_player()));
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::board::{BoardError, Cell, Coord};
+use crate::engine::AmazonsEngine;
+
+/// Steps to the eight neighbouring squares, which are also the queen lines
+pub(crate) const DIRECTIONS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+/// A full turn: an amazon moves from `from` to `to` and shoots at `arrow`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub from: Coord,
+    pub to: Coord,
+    pub arrow: Coord,
+}
+
+impl Move {
+    pub fn new(from: Coord, to: Coord, arrow: Coord) -> Self {
+        Move { from, to, arrow }
+    }
+
+    /// Plays this move on the given board
+    pub fn play<E: AmazonsEngine + ?Sized>(&self, engine: &mut E) -> Result<(), BoardError> {
+        engine.play(self.from, self.to, self.arrow)
+    }
+}
+
+// Calls `f` for every square reachable from `from` along a queen line,
+// treating `vacated` as empty
+fn queen_moves<E, F>(engine: &E, from: Coord, vacated: Option<Coord>, mut f: F)
+where
+    E: AmazonsEngine + ?Sized,
+    F: FnMut(Coord),
+{
+    let (width, height) = (engine.width() as i64, engine.height() as i64);
+    for (dx, dy) in DIRECTIONS {
+        let (dx, dy) = (i64::from(dx), i64::from(dy));
+        let (mut x, mut y) = (from.x as i64 + dx, from.y as i64 + dy);
+        while x >= 0 && y >= 0 && x < width && y < height {
+            let coord = Coord::new(x as u32, y as u32);
+            if Some(coord) != vacated && engine.cell(coord) != Ok(Cell::Empty) {
+                break;
+            }
+            f(coord);
+            x += dx;
+            y += dy;
+        }
+    }
+}
+
+/// Explains why the player to move cannot pick up the piece at `from`
+pub fn check_source<E: AmazonsEngine + ?Sized>(engine: &E, from: Coord) -> Result<(), BoardError> {
+    if engine.cell(from)? != Cell::Amazon(engine.current_player()) {
+        return Err(BoardError::NotOwnPiece(from));
+    }
+    Ok(())
+}
+
+/// Explains why nothing can travel from `from` to `to` along a queen line,
+/// treating `vacated` as empty. Used to tell players why a move or shot was
+/// rejected; the rules engine has the final say.
+pub fn check_line<E: AmazonsEngine + ?Sized>(
+    engine: &E,
+    from: Coord,
+    to: Coord,
+    vacated: Option<Coord>,
+) -> Result<(), BoardError> {
+    engine.coord(from.x, from.y)?;
+    engine.coord(to.x, to.y)?;
+    let (dx, dy) = (to.x as i64 - from.x as i64, to.y as i64 - from.y as i64);
+    if (dx, dy) == (0, 0) || (dx != 0 && dy != 0 && dx.abs() != dy.abs()) {
+        return Err(BoardError::NotQueenLine(from, to));
+    }
+    let (dx, dy) = (dx.signum(), dy.signum());
+    let (mut x, mut y) = (from.x as i64 + dx, from.y as i64 + dy);
+    loop {
+        let coord = Coord::new(x as u32, y as u32);
+        let empty = Some(coord) == vacated || engine.cell(coord)? == Cell::Empty;
+        match (coord == to, empty) {
+            (true, true) => return Ok(()),
+            (true, false) => return Err(BoardError::Occupied(to)),
+            (false, false) => return Err(BoardError::Blocked(coord)),
+            (false, true) => (),
+        }
+        x += dx;
+        y += dy;
+    }
+}
+
+/// Lists the squares holding amazons of the player to move
+pub fn own_amazons<E: AmazonsEngine + ?Sized>(engine: &E) -> Vec<Coord> {
+    let piece = Cell::Amazon(engine.current_player());
+    let mut amazons = vec![];
+    for y in 0..engine.height() {
+        for x in 0..engine.width() {
+            let coord = Coord::new(x, y);
+            if engine.cell(coord) == Ok(piece) {
+                amazons.push(coord);
+            }
+        }
+    }
+    amazons
+}
+
+/// Lists the squares the amazon at `from` can move to
+pub fn destinations<E: AmazonsEngine + ?Sized>(engine: &E, from: Coord) -> Vec<Coord> {
+    let mut squares = vec![];
+    queen_moves(engine, from, None, |to| squares.push(to));
+    squares
+}
+
+/// Lists the squares an amazon moving from `from` to `to` can shoot at;
+/// the square it left counts as empty
+pub fn arrow_targets<E: AmazonsEngine + ?Sized>(engine: &E, from: Coord, to: Coord) -> Vec<Coord> {
+    let mut squares = vec![];
+    queen_moves(engine, to, Some(from), |arrow| squares.push(arrow));
+    squares
+}
+
+/// Lists every legal turn for the player to move
+pub fn legal_moves<E: AmazonsEngine + ?Sized>(engine: &E) -> Vec<Move> {
+    let mut moves = vec![];
+    for from in own_amazons(engine) {
+        for to in destinations(engine, from) {
+            queen_moves(engine, to, Some(from), |arrow| {
+                moves.push(Move::new(from, to, arrow))
+            });
+        }
+    }
+    moves
+}
+
+/// Counts the legal turns for the player to move without listing them
+pub fn count_moves<E: AmazonsEngine + ?Sized>(engine: &E) -> usize {
+    let mut count = 0;
+    for from in own_amazons(engine) {
+        queen_moves(engine, from, None, |to| {
+            queen_moves(engine, to, Some(from), |_| count += 1);
+        });
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+
+    #[test]
+    fn standard_opening_moves() {
+        let board = Board::standard();
+        let moves = legal_moves(&board);
+        assert_eq!(moves.len(), 2176);
+        assert_eq!(count_moves(&board), 2176);
+        for mv in moves.iter().step_by(97) {
+            let mut board = Board::standard();
+            assert_eq!(mv.play(&mut board), Ok(()));
+        }
+    }
+
+    #[test]
+    fn rejection_reasons() {
+        let board = Board::standard();
+        let c = |s: &str| s.parse::<Coord>().unwrap();
+        assert_eq!(
+            check_source(&board, c("g1")),
+            Err(BoardError::NotOwnPiece(c("g1")))
+        );
+        assert_eq!(check_source(&board, c("d1")), Ok(()));
+        assert_eq!(
+            check_line(&board, c("d1"), c("e3"), None),
+            Err(BoardError::NotQueenLine(c("d1"), c("e3")))
+        );
+        assert_eq!(
+            check_line(&board, c("d1"), c("h1"), None),
+            Err(BoardError::Blocked(c("g1")))
+        );
+        assert_eq!(
+            check_line(&board, c("d1"), c("g1"), None),
+            Err(BoardError::Occupied(c("g1")))
+        );
+        assert_eq!(
+            check_line(&board, c("d1"), c("k1"), None),
+            Err(BoardError::OutOfBounds(Coord::new(10, 0)))
+        );
+        assert_eq!(check_line(&board, c("d4"), c("d1"), Some(c("d1"))), Ok(()));
+    }
+
+    #[test]
+    fn vacated_square_is_a_target() {
+        let board = Board::new(2, 1, &[(0, 0)], &[]).unwrap();
+        let moves = legal_moves(&board);
+        let (a, b) = (Coord::new(0, 0), Coord::new(1, 0));
+        assert_eq!(moves, vec![Move::new(a, b, a)]);
+    }
+}
@@ -0,0 +1,495 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Rust implementation of the Amazons library. The functions have the same
+//! signatures as the C functions so that they can be swapped in with the
+//! `pure-rust` feature.
+
+#![allow(non_snake_case)]
+#![allow(non_upper_case_globals)]
+#![allow(clippy::missing_safety_doc)]
+
+use crate::boardstate::Amazons::{
+    BoardState, Square, SquareState, SquareState_ARROW, SquareState_BLACK, SquareState_EMPTY,
+    SquareState_WHITE,
+};
+use crate::movegen::DIRECTIONS;
+use std::os::raw::c_int;
+use std::ptr;
+
+fn size(board: &BoardState) -> usize {
+    (board.boardWidth.max(0) * board.boardHeight.max(0)) as usize
+}
+
+fn in_bounds(board: &BoardState, x: c_int, y: c_int) -> bool {
+    x >= 0 && y >= 0 && x < board.boardWidth && y < board.boardHeight
+}
+
+fn index(board: &BoardState, x: c_int, y: c_int) -> usize {
+    (y * board.boardWidth + x) as usize
+}
+
+unsafe fn cells(board: &BoardState) -> &[SquareState] {
+    if board.board.is_null() {
+        return &[];
+    }
+    std::slice::from_raw_parts(board.board, size(board))
+}
+
+unsafe fn cells_mut(board: &mut BoardState) -> &mut [SquareState] {
+    if board.board.is_null() {
+        return &mut [];
+    }
+    std::slice::from_raw_parts_mut(board.board, size(board))
+}
+
+unsafe fn regions(board: &BoardState) -> &[SquareState] {
+    if board.map.is_null() {
+        return &[];
+    }
+    std::slice::from_raw_parts(board.map, size(board))
+}
+
+unsafe fn regions_mut(board: &mut BoardState) -> &mut [SquareState] {
+    if board.map.is_null() {
+        return &mut [];
+    }
+    std::slice::from_raw_parts_mut(board.map, size(board))
+}
+
+fn alloc(size: usize) -> *mut SquareState {
+    Box::into_raw(vec![SquareState_EMPTY; size].into_boxed_slice()) as *mut SquareState
+}
+
+pub unsafe fn swapPlayer(player: *mut SquareState) {
+    *player = if *player == SquareState_WHITE {
+        SquareState_BLACK
+    } else {
+        SquareState_WHITE
+    };
+}
+
+pub unsafe fn boardstate_init(
+    board: *mut BoardState,
+    wp: c_int,
+    bp: c_int,
+    bw: c_int,
+    bh: c_int,
+    whiteStart: *mut Square,
+    blackStart: *mut Square,
+) {
+    boardstate_free(board);
+    let board = &mut *board;
+    board.whitePieces = wp;
+    board.blackPieces = bp;
+    board.boardWidth = bw;
+    board.boardHeight = bh;
+    board.currentPlayer = SquareState_WHITE;
+    board.board = alloc(size(board));
+    board.map = alloc(size(board));
+    for (start, count, player) in [
+        (whiteStart, wp, SquareState_WHITE),
+        (blackStart, bp, SquareState_BLACK),
+    ] {
+        for i in 0..count.max(0) as usize {
+            let sq = *start.add(i);
+            let i = index(board, sq.x, sq.y);
+            cells_mut(board)[i] = player;
+        }
+    }
+}
+
+pub unsafe fn boardstate_standard(board: *mut BoardState) {
+    let mut white = [
+        Square::new(3, 0),
+        Square::new(0, 3),
+        Square::new(0, 6),
+        Square::new(3, 9),
+    ];
+    let mut black = [
+        Square::new(6, 0),
+        Square::new(9, 3),
+        Square::new(9, 6),
+        Square::new(6, 9),
+    ];
+    boardstate_init(board, 4, 4, 10, 10, white.as_mut_ptr(), black.as_mut_ptr());
+}
+
+pub unsafe fn boardstate_free(board: *mut BoardState) {
+    let board = &mut *board;
+    let size = size(board);
+    for buf in [&mut board.board, &mut board.map] {
+        if !buf.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(*buf, size)));
+            *buf = ptr::null_mut();
+        }
+    }
+}
+
+pub unsafe fn boardstate_winner(
+    board: *mut BoardState,
+    white: *mut c_int,
+    black: *mut c_int,
+) -> SquareState {
+    let board = &mut *board;
+    let squares = cells(board);
+    // Built separately and copied over at the end since the squares are
+    // still borrowed while the regions are found
+    let mut map = vec![SquareState_EMPTY; squares.len()];
+    let mut visited = vec![false; squares.len()];
+    let (mut ws, mut bs) = (0, 0);
+    let mut partitioned = true;
+    for start in 0..squares.len() {
+        if visited[start] || squares[start] == SquareState_ARROW {
+            continue;
+        }
+        // Flood fill the region of non-arrow squares containing this square
+        let mut region = vec![start];
+        let mut stack = vec![start];
+        visited[start] = true;
+        let mut controller = SquareState_EMPTY;
+        let mut empty = 0;
+        while let Some(i) = stack.pop() {
+            match squares[i] {
+                SquareState_EMPTY => empty += 1,
+                player => controller |= player,
+            }
+            let (x, y) = (i as c_int % board.boardWidth, i as c_int / board.boardWidth);
+            for (dx, dy) in DIRECTIONS {
+                let (nx, ny) = (x + dx, y + dy);
+                if !in_bounds(board, nx, ny) {
+                    continue;
+                }
+                let j = index(board, nx, ny);
+                if !visited[j] && squares[j] != SquareState_ARROW {
+                    visited[j] = true;
+                    region.push(j);
+                    stack.push(j);
+                }
+            }
+        }
+        for i in region {
+            map[i] = controller;
+        }
+        match controller {
+            SquareState_WHITE => ws += empty,
+            SquareState_BLACK => bs += empty,
+            SquareState_EMPTY => (),
+            _ => partitioned = false,
+        }
+    }
+    regions_mut(board).copy_from_slice(&map);
+    *white = ws;
+    *black = bs;
+    if !partitioned {
+        SquareState_EMPTY
+    } else if ws > bs {
+        SquareState_WHITE
+    } else if bs > ws {
+        SquareState_BLACK
+    } else {
+        board.currentPlayer
+    }
+}
+
+pub unsafe fn boardstate_squareState(board: *mut BoardState, square: *mut Square) -> SquareState {
+    let board = &*board;
+    let sq = &*square;
+    cells(board)[index(board, sq.x, sq.y)]
+}
+
+pub unsafe fn boardstate_squareController(
+    board: *mut BoardState,
+    square: *mut Square,
+) -> SquareState {
+    let board = &*board;
+    let sq = &*square;
+    regions(board)[index(board, sq.x, sq.y)]
+}
+
+pub unsafe fn playerHasValidMove(board: *mut BoardState, player: SquareState) -> c_int {
+    let board = &*board;
+    let squares = cells(board);
+    for y in 0..board.boardHeight {
+        for x in 0..board.boardWidth {
+            if squares[index(board, x, y)] != player {
+                continue;
+            }
+            for (dx, dy) in DIRECTIONS {
+                let (nx, ny) = (x + dx, y + dy);
+                if in_bounds(board, nx, ny) && squares[index(board, nx, ny)] == SquareState_EMPTY {
+                    return 1;
+                }
+            }
+        }
+    }
+    0
+}
+
+pub unsafe fn isValidMove(board: *mut BoardState, src: *mut Square, dst: *mut Square) -> c_int {
+    let board = &*board;
+    let (src, dst) = (&*src, &*dst);
+    if !in_bounds(board, src.x, src.y) || !in_bounds(board, dst.x, dst.y) {
+        return 0;
+    }
+    let (dx, dy) = (dst.x - src.x, dst.y - src.y);
+    if (dx == 0 && dy == 0) || (dx != 0 && dy != 0 && dx.abs() != dy.abs()) {
+        return 0;
+    }
+    let (sx, sy) = (dx.signum(), dy.signum());
+    let squares = cells(board);
+    let (mut x, mut y) = (src.x, src.y);
+    while (x, y) != (dst.x, dst.y) {
+        x += sx;
+        y += sy;
+        if squares[index(board, x, y)] != SquareState_EMPTY {
+            return 0;
+        }
+    }
+    1
+}
+
+pub unsafe fn amazons_move(board: *mut BoardState, src: *mut Square, dst: *mut Square) -> c_int {
+    if isValidMove(board, src, dst) == 0 {
+        return 0;
+    }
+    let board = &mut *board;
+    let (src, dst) = (
+        index(board, (*src).x, (*src).y),
+        index(board, (*dst).x, (*dst).y),
+    );
+    let squares = cells_mut(board);
+    squares[dst] = squares[src];
+    squares[src] = SquareState_EMPTY;
+    1
+}
+
+pub unsafe fn amazons_shoot(
+    board: *mut BoardState,
+    src: *mut Square,
+    target: *mut Square,
+) -> c_int {
+    if isValidMove(board, src, target) == 0 {
+        return 0;
+    }
+    let board = &mut *board;
+    let target = index(board, (*target).x, (*target).y);
+    cells_mut(board)[target] = SquareState_ARROW;
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::{Board, BoardError, Cell, Coord, Player};
+    use crate::engine::AmazonsEngine;
+
+    #[test]
+    fn blocked_moves_are_rejected() {
+        let mut board = Board::standard();
+        let (src, dst) = (Coord::new(3, 0), Coord::new(3, 5));
+        assert!(board.is_valid_move(src, dst));
+        assert!(!board.is_valid_move(src, Coord::new(4, 2)));
+        assert!(!board.is_valid_move(src, Coord::new(6, 0)));
+        board.play(src, dst, Coord::new(3, 2)).unwrap();
+        assert_eq!(board.cell(Coord::new(3, 2)), Ok(Cell::Arrow));
+        assert_eq!(board.current_player(), Player::Black);
+        let err = board.play(Coord::new(6, 0), Coord::new(6, 9), Coord::new(0, 0));
+        assert_eq!(
+            err,
+            Err(BoardError::IllegalMove(Coord::new(6, 0), Coord::new(6, 9)))
+        );
+    }
+
+    #[test]
+    fn partitioned_board_decides_game() {
+        let mut board = Board::new(3, 1, &[(0, 0)], &[(2, 0)]).unwrap();
+        assert_eq!(board.winner(), Err(BoardError::GameNotOver));
+        board
+            .play(Coord::new(0, 0), Coord::new(1, 0), Coord::new(0, 0))
+            .unwrap();
+        let outcome = board.winner().unwrap();
+        assert_eq!(outcome.winner, Player::White);
+        assert_eq!(outcome.territory, None);
+    }
+}
+
+#[cfg(all(test, feature = "differential"))]
+mod differential {
+    use crate::boardstate::Amazons::{
+        ffi, BoardState, Square, SquareState_BLACK, SquareState_EMPTY, SquareState_WHITE,
+    };
+    use std::ops::{Deref, DerefMut};
+    use std::os::raw::c_int;
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u32) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as u32
+        }
+    }
+
+    fn squares(board: &BoardState) -> Vec<Square> {
+        let mut all = vec![];
+        for y in 0..board.boardHeight as u32 {
+            for x in 0..board.boardWidth as u32 {
+                all.push(Square::new(x, y));
+            }
+        }
+        all
+    }
+
+    unsafe fn compare(c: &mut BoardState, rust: &mut BoardState) {
+        for player in [SquareState_WHITE, SquareState_BLACK] {
+            assert_eq!(
+                ffi::playerHasValidMove(c, player),
+                super::playerHasValidMove(rust, player)
+            );
+        }
+        let (mut cw, mut cb, mut rw, mut rb): (c_int, c_int, c_int, c_int) = (0, 0, 0, 0);
+        assert_eq!(
+            ffi::boardstate_winner(c, &mut cw, &mut cb),
+            super::boardstate_winner(rust, &mut rw, &mut rb)
+        );
+        assert_eq!((cw, cb), (rw, rb));
+        for mut sq in squares(c) {
+            assert_eq!(
+                ffi::boardstate_squareState(c, &mut sq),
+                super::boardstate_squareState(rust, &mut sq)
+            );
+            assert_eq!(
+                ffi::boardstate_squareController(c, &mut sq),
+                super::boardstate_squareController(rust, &mut sq)
+            );
+        }
+    }
+
+    unsafe fn play_random_game(rng: &mut Rng, c: &mut BoardState, rust: &mut BoardState) {
+        loop {
+            compare(c, rust);
+            let player = c.currentPlayer;
+            let all = squares(c);
+            let mut turns = vec![];
+            for mut src in all.iter().copied() {
+                if super::boardstate_squareState(rust, &mut src) != player
+                    || player == SquareState_EMPTY
+                {
+                    continue;
+                }
+                for mut dst in all.iter().copied() {
+                    let valid = ffi::isValidMove(c, &mut src, &mut dst);
+                    assert_eq!(valid, super::isValidMove(rust, &mut src, &mut dst));
+                    if valid == 1 {
+                        turns.push((src, dst));
+                    }
+                }
+            }
+            if turns.is_empty() {
+                return;
+            }
+            let (mut src, mut dst) = turns[rng.below(turns.len() as u32) as usize];
+            assert_eq!(
+                ffi::amazons_move(c, &mut src, &mut dst),
+                super::amazons_move(rust, &mut src, &mut dst)
+            );
+            let mut target = all[rng.below(all.len() as u32) as usize];
+            let shot = ffi::amazons_shoot(c, &mut dst, &mut target);
+            assert_eq!(shot, super::amazons_shoot(rust, &mut dst, &mut target));
+            // Fall back to the square just vacated, which is always a valid shot
+            if shot == 0 {
+                assert_eq!(ffi::amazons_shoot(c, &mut dst, &mut src), 1);
+                assert_eq!(super::amazons_shoot(rust, &mut dst, &mut src), 1);
+            }
+            ffi::swapPlayer(&mut c.currentPlayer);
+            super::swapPlayer(&mut rust.currentPlayer);
+            assert_eq!(c.currentPlayer, rust.currentPlayer);
+        }
+    }
+
+    /// A board allocated by the C library, which must also free it, even when
+    /// a failed assertion unwinds through the test
+    #[derive(Default)]
+    struct CBoard(BoardState);
+
+    impl Deref for CBoard {
+        type Target = BoardState;
+
+        fn deref(&self) -> &BoardState {
+            &self.0
+        }
+    }
+
+    impl DerefMut for CBoard {
+        fn deref_mut(&mut self) -> &mut BoardState {
+            &mut self.0
+        }
+    }
+
+    impl Drop for CBoard {
+        fn drop(&mut self) {
+            unsafe {
+                ffi::boardstate_free(&mut self.0);
+            }
+            // Leaves nothing for the pure-Rust `Drop` of the inner board to free
+            self.0.board = std::ptr::null_mut();
+            self.0.map = std::ptr::null_mut();
+        }
+    }
+
+    #[test]
+    fn standard_games_agree() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..20 {
+            unsafe {
+                let (mut c, mut rust) = (CBoard::default(), BoardState::default());
+                ffi::boardstate_standard(&mut c.0);
+                super::boardstate_standard(&mut rust);
+                play_random_game(&mut rng, &mut c, &mut rust);
+            }
+        }
+    }
+
+    #[test]
+    fn custom_games_agree() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..50 {
+            let (w, h) = (2 + rng.below(7), 2 + rng.below(7));
+            let mut free: Vec<(u32, u32)> =
+                (0..h).flat_map(|y| (0..w).map(move |x| (x, y))).collect();
+            let mut pick = |n: u32| -> Vec<(u32, u32)> {
+                (0..n)
+                    .map(|_| free.swap_remove(rng.below(free.len() as u32) as usize))
+                    .collect()
+            };
+            let white = pick(1 + (w * h / 8).min(3));
+            let black = pick(1 + (w * h / 8).min(3));
+            let to_squares = |starting: &[(u32, u32)]| -> Vec<Square> {
+                starting.iter().map(|&(x, y)| Square::new(x, y)).collect()
+            };
+            let (mut ws, mut bs) = (to_squares(&white), to_squares(&black));
+            let (wp, bp) = (ws.len() as c_int, bs.len() as c_int);
+            let (w, h) = (w as c_int, h as c_int);
+            unsafe {
+                let (mut c, mut rust) = (CBoard::default(), BoardState::default());
+                ffi::boardstate_init(&mut c.0, wp, bp, w, h, ws.as_mut_ptr(), bs.as_mut_ptr());
+                super::boardstate_init(&mut rust, wp, bp, w, h, ws.as_mut_ptr(), bs.as_mut_ptr());
+                assert_eq!(c.currentPlayer, rust.currentPlayer);
+                play_random_game(&mut rng, &mut c, &mut rust);
+            }
+        }
+    }
+}
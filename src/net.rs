@@ -0,0 +1,939 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Games between two instances over TCP. The peers exchange one message per
+//! line:
+//!
+//! ```text
+//! HELLO 1 Player 1
+//! START b 3W2B3/10/10/W8B/10/10/W8B/10/10/3W2B3 w
+//! MOVE d1-d7/g7
+//! ```
+//!
+//! Both sides introduce themselves with the protocol version and their name.
+//! The host then sends the starting position and the side the guest plays,
+//! and from then on each side sends its turns in move notation. A side that
+//! gives up on the connection may say why with `ERROR <reason>`.
+//!
+//! Every turn received is checked against a board kept by the session, so a
+//! peer that sends an illegal turn is disconnected instead of corrupting the
+//! game.
+//!
+//! The same messages are used with `amazons-server`, which hosts many games.
+//! After the greetings a client asks for the list of games with `LIST` and is
+//! answered with `LOBBY <count>` followed by one line per game:
+//!
+//! ```text
+//! GAME <id> <open|playing|over> <starting position> <title>
+//! ```
+//!
+//! `CREATE <position>` opens a new game, confirmed with `CREATED <id>`, in
+//! which the creator plays white; `JOIN <id>` takes the free side of an open
+//! game and `WATCH <id>` follows a game as a spectator. A request that cannot
+//! be granted is answered with `DENIED <reason>`. Once a game begins, the
+//! server introduces the opponent with `HELLO` and sends `START`, followed for
+//! spectators by the turns played so far.
+//!
+//! While waiting for a guest, a host announces its game once a second with a
+//! UDP broadcast to `DISCOVERY_PORT`, which the join dialog listens on:
+//!
+//! ```text
+//! AMAZONS 1 <id> <port> <width> <height> <white amazons> <black amazons> <name>
+//! ```
+
+use crate::board::{BoardError, Cell, Player};
+use crate::engine::AmazonsEngine;
+use crate::movegen::Move;
+use crate::position::Position;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{
+    Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket,
+};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const DEFAULT_PORT: u16 = 7654;
+pub const DISCOVERY_PORT: u16 = 7655;
+
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+/// Games that have not been announced for this long are no longer listed
+const ANNOUNCE_EXPIRY: Duration = Duration::from_secs(4);
+
+/// How long joining waits for the host to accept the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest line accepted from a peer, in bytes
+const MAX_LINE: usize = 4096;
+/// Messages waiting to be written before a peer is taken to have stopped reading
+const MAX_QUEUED: usize = 1024;
+/// How long a write may stall before the connection is closed
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetError {
+    Io(String),
+    /// The peer sent something that does not fit the protocol
+    Protocol(String),
+    IllegalMove(Move, BoardError),
+    /// The peer closed the connection, with its reason if it gave one
+    Closed(Option<String>),
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetError::Io(e) => write!(f, "{}", e),
+            NetError::Protocol(e) => write!(f, "protocol error: {}", e),
+            NetError::IllegalMove(mv, e) => write!(f, "received illegal turn {}: {}", mv, e),
+            NetError::Closed(None) => write!(f, "the other side left"),
+            NetError::Closed(Some(reason)) => write!(f, "the other side left: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for NetError {}
+
+impl From<io::Error> for NetError {
+    fn from(e: io::Error) -> Self {
+        NetError::Io(e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Hello {
+        version: u32,
+        name: String,
+    },
+    /// The starting position and the side played by the receiver, if any
+    Start {
+        side: Option<Player>,
+        position: Position,
+    },
+    Move(Move),
+    Error(String),
+    List,
+    /// The number of `Game` messages that follow
+    Lobby(usize),
+    Game(LobbyGame),
+    Create(Position),
+    Created(u32),
+    Join(u32),
+    Watch(u32),
+    Denied(String),
+}
+
+/// Whether a game on a server can be joined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameStatus {
+    Open,
+    Playing,
+    Over,
+}
+
+/// A game as listed in the lobby of a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LobbyGame {
+    pub id: u32,
+    pub status: GameStatus,
+    pub start: Position,
+    /// The names of the players
+    pub title: String,
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::Hello { version, name } => write!(f, "HELLO {} {}", version, name),
+            Message::Start { side, position } => {
+                let side = match side {
+                    Some(Player::White) => "w",
+                    Some(Player::Black) => "b",
+                    None => "-",
+                };
+                write!(f, "START {} {}", side, position)
+            }
+            Message::Move(mv) => write!(f, "MOVE {}", mv),
+            Message::Error(reason) => write!(f, "ERROR {}", reason),
+            Message::List => write!(f, "LIST"),
+            Message::Lobby(count) => write!(f, "LOBBY {}", count),
+            Message::Game(game) => {
+                let status = match game.status {
+                    GameStatus::Open => "open",
+                    GameStatus::Playing => "playing",
+                    GameStatus::Over => "over",
+                };
+                write!(
+                    f,
+                    "GAME {} {} {} {}",
+                    game.id, status, game.start, game.title
+                )
+            }
+            Message::Create(position) => write!(f, "CREATE {}", position),
+            Message::Created(id) => write!(f, "CREATED {}", id),
+            Message::Join(id) => write!(f, "JOIN {}", id),
+            Message::Watch(id) => write!(f, "WATCH {}", id),
+            Message::Denied(reason) => write!(f, "DENIED {}", reason),
+        }
+    }
+}
+
+impl FromStr for Message {
+    type Err = NetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || NetError::Protocol(format!("invalid message '{}'", s));
+        let (keyword, rest) = s.trim().split_once(' ').unwrap_or((s.trim(), ""));
+        match keyword {
+            "HELLO" => {
+                let (version, name) = rest.split_once(' ').unwrap_or((rest, ""));
+                Ok(Message::Hello {
+                    version: version.parse().map_err(|_| invalid())?,
+                    name: name.to_string(),
+                })
+            }
+            "START" => {
+                let (side, position) = rest.split_once(' ').ok_or_else(invalid)?;
+                let side = match side {
+                    "w" => Some(Player::White),
+                    "b" => Some(Player::Black),
+                    "-" => None,
+                    _ => return Err(invalid()),
+                };
+                let position = position
+                    .parse()
+                    .map_err(|e| NetError::Protocol(format!("invalid position: {}", e)))?;
+                Ok(Message::Start { side, position })
+            }
+            "MOVE" => rest
+                .parse()
+                .map(Message::Move)
+                .map_err(|e| NetError::Protocol(format!("invalid turn '{}': {}", rest, e))),
+            "ERROR" => Ok(Message::Error(rest.to_string())),
+            "LIST" => Ok(Message::List),
+            "LOBBY" => rest.parse().map(Message::Lobby).map_err(|_| invalid()),
+            "GAME" => {
+                // The position string is made of the rows and the side to move
+                let fields: Vec<&str> = rest.splitn(5, ' ').collect();
+                if fields.len() < 4 {
+                    return Err(invalid());
+                }
+                let status = match fields[1] {
+                    "open" => GameStatus::Open,
+                    "playing" => GameStatus::Playing,
+                    "over" => GameStatus::Over,
+                    _ => return Err(invalid()),
+                };
+                Ok(Message::Game(LobbyGame {
+                    id: fields[0].parse().map_err(|_| invalid())?,
+                    status,
+                    start: format!("{} {}", fields[2], fields[3])
+                        .parse()
+                        .map_err(|_| invalid())?,
+                    title: fields.get(4).unwrap_or(&"").to_string(),
+                }))
+            }
+            "CREATE" => rest
+                .parse()
+                .map(Message::Create)
+                .map_err(|e| NetError::Protocol(format!("invalid position: {}", e))),
+            "CREATED" => rest.parse().map(Message::Created).map_err(|_| invalid()),
+            "JOIN" => rest.parse().map(Message::Join).map_err(|_| invalid()),
+            "WATCH" => rest.parse().map(Message::Watch).map_err(|_| invalid()),
+            "DENIED" => Ok(Message::Denied(rest.to_string())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A connection to a peer. Messages are read and written on separate
+/// threads so that neither `poll` nor `send` blocks; dropping the connection
+/// closes it once the messages already sent have been written.
+#[derive(Debug)]
+pub struct Connection {
+    stream: TcpStream,
+    incoming: Receiver<Result<Message, NetError>>,
+    outgoing: SyncSender<String>,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> Result<Self, NetError> {
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let (sender, incoming) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut line = String::new();
+            // A peer cannot make the connection buffer an endless line
+            let read = (&mut reader).take(MAX_LINE as u64 + 1).read_line(&mut line);
+            // Read errors and runaway lines end the connection
+            let (message, stop) = match read {
+                Ok(0) => break,
+                Ok(_) if line.len() > MAX_LINE => {
+                    let reason = format!("line longer than {} bytes", MAX_LINE);
+                    (Err(NetError::Protocol(reason)), true)
+                }
+                Ok(_) if line.trim().is_empty() => continue,
+                Ok(_) => (line.trim_end_matches(['\r', '\n']).parse(), false),
+                Err(e) => (Err(NetError::from(e)), true),
+            };
+            if sender.send(message).is_err() || stop {
+                break;
+            }
+        });
+        let mut writer = stream.try_clone()?;
+        let (outgoing, queue) = mpsc::sync_channel::<String>(MAX_QUEUED);
+        thread::spawn(move || {
+            for line in queue {
+                if writeln!(writer, "{}", line).is_err() {
+                    break;
+                }
+            }
+            // Also ends the reading thread; the peer may already be gone
+            let _ = writer.shutdown(Shutdown::Both);
+        });
+        Ok(Connection {
+            stream,
+            incoming,
+            outgoing,
+        })
+    }
+
+    /// Address of the other side
+    pub fn peer(&self) -> String {
+        self.stream
+            .peer_addr()
+            .map_or_else(|_| "unknown address".to_string(), |addr| addr.to_string())
+    }
+
+    /// Queues a message to be written; fails if the peer has fallen too far
+    /// behind in reading or the connection is closed
+    pub fn send(&mut self, message: &Message) -> Result<(), NetError> {
+        match self.outgoing.try_send(message.to_string()) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                Err(NetError::Io("the other side stopped reading".to_string()))
+            }
+            Err(TrySendError::Disconnected(_)) => Err(NetError::Closed(None)),
+        }
+    }
+
+    /// The next message if one has arrived
+    pub fn poll(&self) -> Option<Result<Message, NetError>> {
+        match self.incoming.try_recv() {
+            Ok(message) => Some(message),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(NetError::Closed(None))),
+        }
+    }
+}
+
+/// Waits for a guest to connect without blocking, over IPv6 and IPv4
+#[derive(Debug)]
+pub struct Listener {
+    listeners: Vec<TcpListener>,
+}
+
+impl Listener {
+    pub fn bind(port: u16) -> Result<Self, NetError> {
+        let mut listeners = vec![];
+        let mut port = port;
+        if let Ok(listener) = TcpListener::bind((Ipv6Addr::UNSPECIFIED, port)) {
+            port = listener.local_addr()?.port();
+            listeners.push(listener);
+        }
+        // Where the IPv6 socket is dual-stack it already takes IPv4 guests
+        // and the port is taken for IPv4 as well
+        match TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)) {
+            Ok(listener) => listeners.push(listener),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && !listeners.is_empty() => (),
+            Err(e) => return Err(e.into()),
+        }
+        for listener in &listeners {
+            listener.set_nonblocking(true)?;
+        }
+        Ok(Listener { listeners })
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        let listener = self.listeners.first()?;
+        listener.local_addr().ok().map(|addr| addr.port())
+    }
+
+    /// A connection if a guest has arrived
+    pub fn accept(&self) -> Option<Result<Connection, NetError>> {
+        self.listeners
+            .iter()
+            .find_map(|listener| match listener.accept() {
+                Ok((stream, _)) => Some(
+                    stream
+                        .set_nonblocking(false)
+                        .map_err(NetError::from)
+                        .and_then(|_| Connection::new(stream)),
+                ),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => None,
+                Err(e) => Some(Err(e.into())),
+            })
+    }
+}
+
+/// Connects to a host on a separate thread
+#[derive(Debug)]
+pub struct Dialer {
+    result: Receiver<Result<Connection, NetError>>,
+}
+
+impl Dialer {
+    /// Starts connecting to `address`, given as `host` or `host:port`; IPv6
+    /// addresses take the form `[addr]:port` or `addr` alone
+    pub fn connect(address: &str) -> Self {
+        let address = with_port(address);
+        let (sender, result) = mpsc::channel();
+        thread::spawn(move || {
+            let connect = || -> Result<Connection, NetError> {
+                let mut last = NetError::Io(format!("could not resolve {}", address));
+                for addr in address.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                        Ok(stream) => return Connection::new(stream),
+                        Err(e) => last = e.into(),
+                    }
+                }
+                Err(last)
+            };
+            // Nobody is waiting if joining was cancelled
+            let _ = sender.send(connect());
+        });
+        Dialer { result }
+    }
+
+    /// The connection once the attempt has finished
+    pub fn try_connection(&self) -> Option<Result<Connection, NetError>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                Some(Err(NetError::Io("connecting failed".to_string())))
+            }
+        }
+    }
+}
+
+// Adds the default port to an address without one. IPv6 addresses need
+// brackets to be given a port, so bare ones never have one.
+fn with_port(address: &str) -> String {
+    let address = address.trim();
+    let bracketed = address.starts_with('[');
+    if bracketed && address.ends_with(']') {
+        format!("{}:{}", address, DEFAULT_PORT)
+    } else if !bracketed && address.matches(':').count() > 1 {
+        format!("[{}]:{}", address, DEFAULT_PORT)
+    } else if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("{}:{}", address, DEFAULT_PORT)
+    }
+}
+
+/// Description of a hosted game broadcast on the local network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    /// Tells announcements of the same game arriving by different routes apart
+    pub id: u64,
+    /// TCP port on which the host accepts the guest
+    pub port: u16,
+    pub width: u32,
+    pub height: u32,
+    pub white: u32,
+    pub black: u32,
+    pub name: String,
+}
+
+impl Announcement {
+    pub fn new<E: AmazonsEngine>(name: &str, port: u16, start: &E) -> Self {
+        let position = Position::from_engine(start);
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_nanos() as u64);
+        Announcement {
+            id: id ^ port as u64,
+            port,
+            width: position.width,
+            height: position.height,
+            white: position.squares(Cell::Amazon(Player::White)).len() as u32,
+            black: position.squares(Cell::Amazon(Player::Black)).len() as u32,
+            name: name.replace(['\r', '\n'], " "),
+        }
+    }
+}
+
+impl fmt::Display for Announcement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "AMAZONS {} {} {} {} {} {} {} {}",
+            PROTOCOL_VERSION,
+            self.id,
+            self.port,
+            self.width,
+            self.height,
+            self.white,
+            self.black,
+            self.name
+        )
+    }
+}
+
+impl FromStr for Announcement {
+    type Err = NetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || NetError::Protocol(format!("invalid announcement '{}'", s));
+        let rest = s.trim().strip_prefix("AMAZONS ").ok_or_else(invalid)?;
+        // The name may contain spaces, so it takes the rest of the line
+        let fields: Vec<&str> = rest.splitn(8, ' ').collect();
+        if fields.len() < 7 || fields[0] != PROTOCOL_VERSION.to_string() {
+            return Err(invalid());
+        }
+        let number = |i: usize| fields[i].parse::<u32>().map_err(|_| invalid());
+        Ok(Announcement {
+            id: fields[1].parse().map_err(|_| invalid())?,
+            port: fields[2].parse().map_err(|_| invalid())?,
+            width: number(3)?,
+            height: number(4)?,
+            white: number(5)?,
+            black: number(6)?,
+            name: fields.get(7).unwrap_or(&"").to_string(),
+        })
+    }
+}
+
+/// Broadcasts a hosted game until dropped
+#[derive(Debug)]
+pub struct Announcer {
+    socket: UdpSocket,
+    message: String,
+    targets: Vec<SocketAddr>,
+    last: Option<Instant>,
+}
+
+impl Announcer {
+    pub fn new(announcement: &Announcement, discovery_port: u16) -> Result<Self, NetError> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_broadcast(true)?;
+        // Broadcasts do not always reach other programs on the same machine
+        let targets = vec![
+            SocketAddr::from((Ipv4Addr::BROADCAST, discovery_port)),
+            SocketAddr::from((Ipv4Addr::LOCALHOST, discovery_port)),
+        ];
+        Ok(Announcer {
+            socket,
+            message: announcement.to_string(),
+            targets,
+            last: None,
+        })
+    }
+
+    /// Sends the announcement if it is time to repeat it
+    pub fn tick(&mut self) {
+        if self
+            .last
+            .is_some_and(|last| last.elapsed() < ANNOUNCE_INTERVAL)
+        {
+            return;
+        }
+        self.last = Some(Instant::now());
+        for target in &self.targets {
+            // Without a network only the local announcement gets through
+            let _ = self.socket.send_to(self.message.as_bytes(), target);
+        }
+    }
+}
+
+/// A game heard on the local network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredGame {
+    /// Where to connect to join the game
+    pub address: SocketAddr,
+    pub announcement: Announcement,
+    seen: Instant,
+}
+
+/// Listens for announced games
+#[derive(Debug)]
+pub struct Browser {
+    socket: UdpSocket,
+    games: Vec<DiscoveredGame>,
+}
+
+impl Browser {
+    pub fn bind(discovery_port: u16) -> Result<Self, NetError> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, discovery_port))?;
+        socket.set_nonblocking(true)?;
+        Ok(Browser {
+            socket,
+            games: vec![],
+        })
+    }
+
+    /// The games announced recently, oldest discovery first
+    pub fn poll(&mut self) -> &[DiscoveredGame] {
+        let mut buffer = [0; 512];
+        while let Ok((length, sender)) = self.socket.recv_from(&mut buffer) {
+            let text = String::from_utf8_lossy(&buffer[..length]);
+            let announcement: Announcement = match text.parse() {
+                Ok(announcement) => announcement,
+                Err(_) => continue,
+            };
+            let address = SocketAddr::new(sender.ip(), announcement.port);
+            let known = self
+                .games
+                .iter_mut()
+                .find(|game| game.announcement.id == announcement.id);
+            match known {
+                Some(game) => {
+                    game.seen = Instant::now();
+                    game.announcement = announcement;
+                }
+                None => self.games.push(DiscoveredGame {
+                    address,
+                    announcement,
+                    seen: Instant::now(),
+                }),
+            }
+        }
+        self.games
+            .retain(|game| game.seen.elapsed() < ANNOUNCE_EXPIRY);
+        &self.games
+    }
+}
+
+/// What happened in a session since it was last polled
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event<E> {
+    /// The game can begin from this position, with the local side if any
+    Started {
+        board: E,
+        local: Option<Player>,
+        peer: String,
+    },
+    /// The other side played a legal turn
+    Move(Move),
+    /// The games on the server
+    Lobby(Vec<LobbyGame>),
+    /// The server opened a game for us, which starts once someone joins
+    Created(u32),
+    /// The server refused a request
+    Denied(String),
+}
+
+/// One side of a game over a connection
+#[derive(Debug)]
+pub struct Session<E> {
+    connection: Connection,
+    name: String,
+    peer: Option<String>,
+    local: Option<Player>,
+    board: Option<E>,
+    started: bool,
+    // Lobby being received: the number of games announced and those so far
+    lobby: Option<(usize, Vec<LobbyGame>)>,
+}
+
+impl<E: AmazonsEngine + Clone> Session<E> {
+    /// Starts a game from `start` with the guest playing `guest`
+    pub fn host(
+        mut connection: Connection,
+        name: &str,
+        start: &E,
+        guest: Player,
+    ) -> Result<Self, NetError> {
+        connection.send(&hello(name))?;
+        connection.send(&Message::Start {
+            side: Some(guest),
+            position: Position::from_engine(start),
+        })?;
+        Ok(Session {
+            connection,
+            name: name.to_string(),
+            peer: None,
+            local: Some(guest.opponent()),
+            board: Some(start.clone()),
+            started: false,
+            lobby: None,
+        })
+    }
+
+    /// Joins the game of the host at the other end of the connection, or
+    /// enters the lobby if it is a server
+    pub fn join(mut connection: Connection, name: &str) -> Result<Self, NetError> {
+        connection.send(&hello(name))?;
+        Ok(Session {
+            connection,
+            name: name.to_string(),
+            peer: None,
+            local: None,
+            board: None,
+            started: false,
+            lobby: None,
+        })
+    }
+
+    /// Sends a lobby request to a server
+    pub fn request(&mut self, message: &Message) -> Result<(), NetError> {
+        self.connection.send(message)
+    }
+
+    /// The name we introduced ourselves with
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The side played here, or `None` when only watching
+    pub fn local_player(&self) -> Option<Player> {
+        self.local
+    }
+
+    pub fn peer_name(&self) -> Option<&str> {
+        self.peer.as_deref()
+    }
+
+    /// Handles the messages that have arrived. An error ends the session; the
+    /// peer is told why if the connection still works.
+    pub fn poll(&mut self) -> Option<Result<Event<E>, NetError>> {
+        while let Some(message) = self.connection.poll() {
+            let result = message.and_then(|message| self.receive(message));
+            match result {
+                Ok(None) => continue,
+                Ok(Some(event)) => return Some(Ok(event)),
+                Err(e) => {
+                    if let NetError::Protocol(_) | NetError::IllegalMove(..) = e {
+                        let _ = self.connection.send(&Message::Error(e.to_string()));
+                    }
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+
+    fn receive(&mut self, message: Message) -> Result<Option<Event<E>>, NetError> {
+        match message {
+            Message::Hello { version, name } => {
+                if version != PROTOCOL_VERSION {
+                    return Err(NetError::Protocol(format!(
+                        "the other side speaks version {}, expected {}",
+                        version, PROTOCOL_VERSION
+                    )));
+                }
+                self.peer = Some(name);
+            }
+            Message::Start { side, position } if self.board.is_none() => {
+                let board = position.to_engine().map_err(|e| {
+                    NetError::Protocol(format!("cannot set up the position: {}", e))
+                })?;
+                self.board = Some(board);
+                self.local = side;
+            }
+            Message::Start { .. } => {
+                return Err(NetError::Protocol(
+                    "the game was already set up".to_string(),
+                ))
+            }
+            Message::Move(mv) => {
+                let board = match (&mut self.board, self.started) {
+                    (Some(board), true) => board,
+                    _ => return Err(NetError::Protocol("turn before the game".to_string())),
+                };
+                if Some(board.current_player()) == self.local {
+                    return Err(NetError::Protocol(format!("{} played out of turn", mv)));
+                }
+                // Only a fully legal turn touches the board
+                let mut after = board.clone();
+                mv.play(&mut after)
+                    .map_err(|e| NetError::IllegalMove(mv, e))?;
+                *board = after;
+                return Ok(Some(Event::Move(mv)));
+            }
+            Message::Error(reason) => return Err(NetError::Closed(Some(reason))),
+            Message::Lobby(count) => {
+                self.lobby = Some((count, vec![]));
+                return Ok(self.lobby_complete());
+            }
+            Message::Game(game) => {
+                match &mut self.lobby {
+                    Some((_, games)) => games.push(game),
+                    None => return Err(NetError::Protocol("game outside the lobby".to_string())),
+                }
+                return Ok(self.lobby_complete());
+            }
+            Message::Created(id) => return Ok(Some(Event::Created(id))),
+            Message::Denied(reason) => return Ok(Some(Event::Denied(reason))),
+            Message::List | Message::Create(_) | Message::Join(_) | Message::Watch(_) => {
+                return Err(NetError::Protocol(
+                    "lobby requests go to a server".to_string(),
+                ))
+            }
+        }
+        // The game begins once both the greeting and the position are known
+        match (&self.peer, &self.board) {
+            (Some(peer), Some(board)) if !self.started => {
+                self.started = true;
+                Ok(Some(Event::Started {
+                    board: board.clone(),
+                    local: self.local,
+                    peer: peer.clone(),
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn lobby_complete(&mut self) -> Option<Event<E>> {
+        match &self.lobby {
+            Some((count, games)) if games.len() >= *count => {
+                let games = self.lobby.take().map(|(_, games)| games)?;
+                Some(Event::Lobby(games))
+            }
+            _ => None,
+        }
+    }
+
+    /// Plays a turn of the local side and sends it to the peer
+    pub fn send_move(&mut self, mv: Move) -> Result<(), NetError> {
+        let board = match &mut self.board {
+            Some(board) if self.started && Some(board.current_player()) == self.local => board,
+            _ => return Err(NetError::Protocol("not our turn".to_string())),
+        };
+        let mut after = board.clone();
+        mv.play(&mut after)
+            .map_err(|e| NetError::IllegalMove(mv, e))?;
+        *board = after;
+        self.connection.send(&Message::Move(mv))
+    }
+}
+
+fn hello(name: &str) -> Message {
+    Message::Hello {
+        version: PROTOCOL_VERSION,
+        // Names cannot span lines
+        name: name.replace(['\r', '\n'], " "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{Board, Coord};
+    use std::time::Instant;
+
+    fn wait<E: AmazonsEngine + Clone>(session: &mut Session<E>) -> Result<Event<E>, NetError> {
+        let start = Instant::now();
+        loop {
+            if let Some(result) = session.poll() {
+                return result;
+            }
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn discovery() {
+        let mut browser = Browser::bind(0).unwrap();
+        let port = browser.socket.local_addr().unwrap().port();
+        let board = Board::new(6, 5, &[(0, 0)], &[(5, 4), (5, 0)]).unwrap();
+        let announcement = Announcement::new("Ann's game", 4321, &board);
+        assert_eq!(announcement.to_string().parse(), Ok(announcement.clone()));
+        let mut announcer = Announcer::new(&announcement, port).unwrap();
+        let start = Instant::now();
+        while browser.poll().is_empty() {
+            announcer.tick();
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(5));
+        }
+        let game = &browser.poll()[0];
+        assert_eq!(game.announcement, announcement);
+        assert_eq!((game.announcement.width, game.announcement.black), (6, 2));
+        assert_eq!(game.address.port(), 4321);
+    }
+
+    #[test]
+    fn addresses_get_default_port() {
+        assert_eq!(with_port("example.org"), "example.org:7654");
+        assert_eq!(with_port("192.168.1.5:9000"), "192.168.1.5:9000");
+        assert_eq!(with_port("[::1]:9000"), "[::1]:9000");
+        assert_eq!(with_port("[::1]"), "[::1]:7654");
+        assert_eq!(with_port("fe80::1"), "[fe80::1]:7654");
+        assert_eq!(with_port(" ::1 "), "[::1]:7654");
+    }
+
+    // The host and guest ends of a connection to the listener at `address`
+    fn connect(listener: &Listener, address: &str) -> (Connection, Connection) {
+        let dialer = Dialer::connect(&format!("{}:{}", address, listener.port().unwrap()));
+        let start = Instant::now();
+        loop {
+            if let (Some(host), Some(guest)) = (listener.accept(), dialer.try_connection()) {
+                return (host.unwrap(), guest.unwrap());
+            }
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn loopback_session() {
+        let listener = Listener::bind(0).unwrap();
+        // Guests can use either protocol, where the system has IPv6 at all
+        if TcpListener::bind((Ipv6Addr::LOCALHOST, 0)).is_ok() {
+            let (mut host, guest) = connect(&listener, "[::1]");
+            host.send(&Message::List).unwrap();
+            let start = Instant::now();
+            let received = loop {
+                if let Some(message) = guest.poll() {
+                    break message;
+                }
+                assert!(start.elapsed() < Duration::from_secs(10));
+                thread::sleep(Duration::from_millis(5));
+            };
+            assert!(matches!(received, Ok(Message::List)));
+        }
+        let (host, guest) = connect(&listener, "127.0.0.1");
+        let board = Board::standard();
+        let mut host = Session::host(host, "Ann", &board, Player::Black).unwrap();
+        let mut guest = Session::<Board>::join(guest, "Bo").unwrap();
+        match wait(&mut guest) {
+            Ok(Event::Started { local, peer, .. }) => {
+                assert_eq!(local, Some(Player::Black));
+                assert_eq!(peer, "Ann");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(wait(&mut host), Ok(Event::Started { .. })));
+
+        let mv: Move = "d1-d7/g7".parse().unwrap();
+        assert!(guest.send_move(mv).is_err());
+        host.send_move(mv).unwrap();
+        assert!(matches!(wait(&mut guest), Ok(Event::Move(m)) if m == mv));
+
+        // A turn the rules reject is refused and the sender is told why
+        let illegal = Move::new(Coord::new(0, 6), Coord::new(9, 6), Coord::new(0, 6));
+        guest.connection.send(&Message::Move(illegal)).unwrap();
+        assert!(matches!(wait(&mut host), Err(NetError::IllegalMove(..))));
+        assert!(matches!(wait(&mut guest), Err(NetError::Closed(Some(_)))));
+    }
+}
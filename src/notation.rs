@@ -0,0 +1,157 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Text notation for squares and turns. Squares are written as a file letter
+//! for the column followed by a rank number for the row, so that `(0, 0)` is
+//! `a1` and `(3, 9)` is `d10`. A turn is written as the starting square, the
+//! destination and the arrow target, e.g. `d1-d7/g7`.
+
+use crate::board::Coord;
+use crate::movegen::Move;
+use std::fmt;
+use std::str::FromStr;
+
+/// Largest board dimension that can be written in this notation
+pub const MAX_SIZE: u32 = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Empty,
+    InvalidFile(char),
+    InvalidRank(String),
+    MissingDestination,
+    MissingArrow,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "expected a square such as a1"),
+            ParseError::InvalidFile(c) => write!(
+                f,
+                "'{}' is not a file; expected a letter from a to {}",
+                c,
+                file_letter(MAX_SIZE - 1)
+            ),
+            ParseError::InvalidRank(rank) => write!(
+                f,
+                "'{}' is not a rank; expected a number from 1 to {}",
+                rank, MAX_SIZE
+            ),
+            ParseError::MissingDestination => {
+                write!(f, "expected '-' followed by the destination square")
+            }
+            ParseError::MissingArrow => write!(f, "expected '/' followed by the arrow target"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub(crate) fn file_letter(x: u32) -> char {
+    (b'a' + x as u8) as char
+}
+
+impl fmt::Display for Coord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", file_letter(self.x), self.y + 1)
+    }
+}
+
+impl FromStr for Coord {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.trim().chars();
+        let file = chars.next().ok_or(ParseError::Empty)?;
+        let x = match file.to_ascii_lowercase() {
+            c @ 'a'..='z' if (c as u32 - 'a' as u32) < MAX_SIZE => c as u32 - 'a' as u32,
+            _ => return Err(ParseError::InvalidFile(file)),
+        };
+        let rank = chars.as_str();
+        let y = match rank.parse::<u32>() {
+            Ok(y) if (1..=MAX_SIZE).contains(&y) && !rank.starts_with('+') => y - 1,
+            _ => return Err(ParseError::InvalidRank(rank.to_string())),
+        };
+        Ok(Coord::new(x, y))
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}/{}", self.from, self.to, self.arrow)
+    }
+}
+
+impl FromStr for Move {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (from, rest) = s.split_once('-').ok_or_else(|| {
+            s.parse::<Coord>()
+                .err()
+                .unwrap_or(ParseError::MissingDestination)
+        })?;
+        let (to, arrow) = rest.split_once('/').ok_or_else(|| {
+            rest.parse::<Coord>()
+                .err()
+                .unwrap_or(ParseError::MissingArrow)
+        })?;
+        Ok(Move::new(from.parse()?, to.parse()?, arrow.parse()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for x in 0..MAX_SIZE {
+            for y in 0..MAX_SIZE {
+                let coord = Coord::new(x, y);
+                assert_eq!(coord.to_string().parse(), Ok(coord));
+            }
+        }
+        let mv = Move::new(Coord::new(3, 0), Coord::new(3, 6), Coord::new(6, 6));
+        assert_eq!(mv.to_string(), "d1-d7/g7");
+        assert_eq!("d1-d7/g7".parse(), Ok(mv));
+        assert_eq!(" D1-d7/G7 ".parse(), Ok(mv));
+        assert_eq!(Coord::new(19, 19).to_string(), "t20");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!("".parse::<Move>(), Err(ParseError::Empty));
+        assert_eq!("d1".parse::<Move>(), Err(ParseError::MissingDestination));
+        assert_eq!("d1-d7".parse::<Move>(), Err(ParseError::MissingArrow));
+        assert_eq!(
+            "u1-d7/g7".parse::<Move>(),
+            Err(ParseError::InvalidFile('u'))
+        );
+        assert_eq!(
+            "d1-d21/g7".parse::<Move>(),
+            Err(ParseError::InvalidRank("21".to_string()))
+        );
+        assert_eq!(
+            "d1-d7/g".parse::<Move>(),
+            Err(ParseError::InvalidRank(String::new()))
+        );
+        assert_eq!(
+            "d0-d7/g7".parse::<Move>(),
+            Err(ParseError::InvalidRank("0".to_string()))
+        );
+    }
+}
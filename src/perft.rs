@@ -0,0 +1,75 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Counting of legal turn sequences, used to check move generation and
+//! the rules implementation against known numbers
+
+use crate::engine::AmazonsEngine;
+use crate::movegen::{count_moves, legal_moves, Move};
+
+/// Counts the legal turn sequences of the given length from a position
+pub fn perft<E: AmazonsEngine + Clone>(engine: &E, depth: u32) -> u64 {
+    match depth {
+        0 => 1,
+        1 => count_moves(engine) as u64,
+        _ => legal_moves(engine)
+            .into_iter()
+            .map(|mv| perft(&after(engine, mv), depth - 1))
+            .sum(),
+    }
+}
+
+/// Like `perft`, but broken down by the first turn. The depth must be at
+/// least 1 since there is no first turn to break down at depth 0, so the
+/// counts only add up to `perft` from there.
+pub fn divide<E: AmazonsEngine + Clone>(engine: &E, depth: u32) -> Vec<(Move, u64)> {
+    if depth == 0 {
+        return vec![];
+    }
+    legal_moves(engine)
+        .into_iter()
+        .map(|mv| (mv, perft(&after(engine, mv), depth - 1)))
+        .collect()
+}
+
+fn after<E: AmazonsEngine + Clone>(engine: &E, mv: Move) -> E {
+    let mut next = engine.clone();
+    mv.play(&mut next)
+        .expect("generated move rejected by the rules engine");
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+
+    #[test]
+    fn standard_perft() {
+        let board = Board::standard();
+        assert_eq!(perft(&board, 0), 1);
+        assert_eq!(perft(&board, 1), 2176);
+        assert_eq!(perft(&board, 2), 4_307_152);
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let board = Board::new(4, 4, &[(0, 0)], &[(3, 3)]).unwrap();
+        for depth in 1..=3 {
+            let total: u64 = divide(&board, depth).iter().map(|(_, n)| n).sum();
+            assert_eq!(total, perft(&board, depth));
+        }
+        assert!(divide(&board, 0).is_empty());
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! One-line position strings in the style of chess FEN. The rows are listed
+//! from rank 1 to the last rank, separated by `/`; in each row `W` and `B`
+//! are white and black amazons, `x` is an arrow, and a number is a run of
+//! empty squares. The board dimensions follow from the number and length of
+//! the rows. The side to move comes last, `w` or `b`. The standard opening is
+//!
+//! ```text
+//! 3W2B3/10/10/W8B/10/10/W8B/10/10/3W2B3 w
+//! ```
+
+use crate::board::{BoardError, Cell, Coord, Player};
+use crate::engine::AmazonsEngine;
+use crate::notation::MAX_SIZE;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PositionError {
+    /// The string does not consist of rows followed by the side to move
+    Format,
+    /// A row (counting from 1) contains something other than pieces and numbers
+    InvalidRow(usize, String),
+    /// A row has a different length than the first one
+    RowLength {
+        row: usize,
+        expected: u32,
+        found: u32,
+    },
+    TooLarge,
+    InvalidSide(String),
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PositionError::Format => write!(f, "expected rows separated by '/' and a side to move"),
+            PositionError::InvalidRow(row, content) => {
+                write!(f, "row {} ('{}') is not valid", row, content)
+            }
+            PositionError::RowLength {
+                row,
+                expected,
+                found,
+            } => write!(
+                f,
+                "row {} has {} squares, expected {}",
+                row, found, expected
+            ),
+            PositionError::TooLarge => {
+                write!(f, "boards are limited to {}x{} squares", MAX_SIZE, MAX_SIZE)
+            }
+            PositionError::InvalidSide(side) => {
+                write!(f, "'{}' is not a side to move; expected w or b", side)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PositionError {}
+
+/// Contents of every square and the side to move
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    pub width: u32,
+    pub height: u32,
+    /// Squares in row-major order
+    pub cells: Vec<Cell>,
+    pub to_move: Player,
+}
+
+impl Position {
+    pub fn from_engine<E: AmazonsEngine + ?Sized>(engine: &E) -> Self {
+        let mut cells = Vec::with_capacity((engine.width() * engine.height()) as usize);
+        for y in 0..engine.height() {
+            for x in 0..engine.width() {
+                cells.push(engine.cell(Coord::new(x, y)).unwrap_or(Cell::Empty));
+            }
+        }
+        Position {
+            width: engine.width(),
+            height: engine.height(),
+            cells,
+            to_move: engine.current_player(),
+        }
+    }
+
+    /// Squares holding the given contents
+    pub fn squares(&self, cell: Cell) -> Vec<Coord> {
+        (0..self.cells.len() as u32)
+            .filter(|i| self.cells[*i as usize] == cell)
+            .map(|i| Coord::new(i % self.width, i / self.width))
+            .collect()
+    }
+
+    /// Builds a board in this position directly, without playing any moves
+    pub fn to_engine<E: AmazonsEngine>(&self) -> Result<E, BoardError> {
+        setup(
+            self.width,
+            self.height,
+            &self.squares(Cell::Amazon(Player::White)),
+            &self.squares(Cell::Amazon(Player::Black)),
+            &self.squares(Cell::Arrow),
+            self.to_move,
+        )
+    }
+}
+
+/// Creates a board with the given pieces and arrows and the given side to move
+pub fn setup<E: AmazonsEngine>(
+    width: u32,
+    height: u32,
+    white: &[Coord],
+    black: &[Coord],
+    arrows: &[Coord],
+    to_move: Player,
+) -> Result<E, BoardError> {
+    let to_pairs =
+        |coords: &[Coord]| -> Vec<(u32, u32)> { coords.iter().map(|c| (c.x, c.y)).collect() };
+    let mut engine = E::new(width, height, &to_pairs(white), &to_pairs(black))?;
+    for arrow in arrows {
+        engine.place_arrow(*arrow)?;
+    }
+    if engine.current_player() != to_move {
+        engine.swap_player();
+    }
+    Ok(engine)
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (y, row) in self.cells.chunks(self.width.max(1) as usize).enumerate() {
+            if y > 0 {
+                write!(f, "/")?;
+            }
+            let mut empty = 0;
+            for cell in row {
+                let piece = match cell {
+                    Cell::Empty => {
+                        empty += 1;
+                        continue;
+                    }
+                    Cell::Arrow => 'x',
+                    Cell::Amazon(Player::White) => 'W',
+                    Cell::Amazon(Player::Black) => 'B',
+                };
+                if empty > 0 {
+                    write!(f, "{}", empty)?;
+                    empty = 0;
+                }
+                write!(f, "{}", piece)?;
+            }
+            if empty > 0 {
+                write!(f, "{}", empty)?;
+            }
+        }
+        let side = match self.to_move {
+            Player::White => 'w',
+            Player::Black => 'b',
+        };
+        write!(f, " {}", side)
+    }
+}
+
+impl FromStr for Position {
+    type Err = PositionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let (rows, side) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(rows), Some(side), None) => (rows, side),
+            _ => return Err(PositionError::Format),
+        };
+        let to_move = match side {
+            "w" => Player::White,
+            "b" => Player::Black,
+            _ => return Err(PositionError::InvalidSide(side.to_string())),
+        };
+        let mut cells = vec![];
+        let mut width = None;
+        let mut height = 0;
+        for (i, row) in rows.split('/').enumerate() {
+            let invalid = || PositionError::InvalidRow(i + 1, row.to_string());
+            let start = cells.len();
+            let mut empty = String::new();
+            for c in row.chars().chain(std::iter::once(' ')) {
+                if c.is_ascii_digit() {
+                    empty.push(c);
+                    continue;
+                }
+                if !empty.is_empty() {
+                    let run: u32 = empty.parse().map_err(|_| invalid())?;
+                    if run == 0 || run > MAX_SIZE {
+                        return Err(invalid());
+                    }
+                    cells.extend((0..run).map(|_| Cell::Empty));
+                    empty.clear();
+                }
+                cells.push(match c {
+                    'W' => Cell::Amazon(Player::White),
+                    'B' => Cell::Amazon(Player::Black),
+                    'x' => Cell::Arrow,
+                    ' ' => break,
+                    _ => return Err(invalid()),
+                });
+            }
+            let length = (cells.len() - start) as u32;
+            if length == 0 {
+                return Err(invalid());
+            }
+            if length > MAX_SIZE {
+                return Err(PositionError::TooLarge);
+            }
+            match width {
+                None => width = Some(length),
+                Some(expected) if expected != length => {
+                    return Err(PositionError::RowLength {
+                        row: i + 1,
+                        expected,
+                        found: length,
+                    })
+                }
+                _ => (),
+            }
+            height += 1;
+            if height > MAX_SIZE {
+                return Err(PositionError::TooLarge);
+            }
+        }
+        Ok(Position {
+            width: width.unwrap_or(0),
+            height,
+            cells,
+            to_move,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+
+    const STANDARD: &str = "3W2B3/10/10/W8B/10/10/W8B/10/10/3W2B3 w";
+
+    #[test]
+    fn standard_position() {
+        let board = Board::standard();
+        assert_eq!(Position::from_engine(&board).to_string(), STANDARD);
+        let position: Position = STANDARD.parse().unwrap();
+        assert_eq!(position, Position::from_engine(&board));
+    }
+
+    #[test]
+    fn round_trip_with_arrows() {
+        let mut board = Board::standard();
+        "d1-d7/g7"
+            .parse::<crate::movegen::Move>()
+            .unwrap()
+            .play(&mut board)
+            .unwrap();
+        let text = Position::from_engine(&board).to_string();
+        assert_eq!(text, "6B3/10/10/W8B/10/10/W2W2x2B/10/10/3W2B3 b");
+        let rebuilt: Board = text.parse::<Position>().unwrap().to_engine().unwrap();
+        assert_eq!(rebuilt.cell(Coord::new(6, 6)), Ok(Cell::Arrow));
+        assert_eq!(rebuilt.current_player(), Player::Black);
+        assert_eq!(Position::from_engine(&rebuilt).to_string(), text);
+    }
+
+    #[test]
+    fn invalid_positions() {
+        assert_eq!("3W2B3/10".parse::<Position>(), Err(PositionError::Format));
+        assert_eq!(
+            "3W2B3/9 w".parse::<Position>(),
+            Err(PositionError::RowLength {
+                row: 2,
+                expected: 10,
+                found: 9
+            })
+        );
+        assert_eq!(
+            "3W2Q3 w".parse::<Position>(),
+            Err(PositionError::InvalidRow(1, "3W2Q3".to_string()))
+        );
+        assert_eq!(
+            "21 w".parse::<Position>().unwrap_err(),
+            PositionError::InvalidRow(1, "21".to_string())
+        );
+        assert_eq!("W20 w".parse::<Position>(), Err(PositionError::TooLarge));
+        assert_eq!("20W w".parse::<Position>(), Err(PositionError::TooLarge));
+        assert_eq!(
+            "10 x".parse::<Position>(),
+            Err(PositionError::InvalidSide("x".to_string()))
+        );
+    }
+}
@@ -0,0 +1,486 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Game records in a PGN-like text format: a header of tags followed by
+//! the numbered turns in move notation. Games that start from a set-up
+//! position also carry an `Arrows` tag and, if black moves first, a
+//! `ToMove "b"` tag. Quotes and backslashes in player names are escaped with
+//! a backslash.
+//!
+//! ```text
+//! [Size "10x10"]
+//! [White "Alice"]
+//! [Black "Bob"]
+//! [WhiteStart "d1 a4 a7 d10"]
+//! [BlackStart "g1 j4 j7 g10"]
+//! [Result "*"]
+//!
+//! 1. d1-d7/g7
+//! 2. g10-g8/e6
+//! ```
+
+use crate::board::{BoardError, Cell, Coord, Player};
+use crate::engine::AmazonsEngine;
+use crate::history::History;
+use crate::movegen::Move;
+use crate::notation::{ParseError, MAX_SIZE};
+use crate::position::{setup, Position};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordError {
+    /// A line of the record could not be read
+    Syntax {
+        line: usize,
+        reason: String,
+    },
+    MissingTag(&'static str),
+    /// The starting position is not a valid board
+    Start(BoardError),
+    /// The given turn (counting from 1) is not legal in the position reached so far
+    IllegalMove {
+        turn: usize,
+        mv: Move,
+        error: BoardError,
+    },
+    /// The recorded result does not match the position reached by the moves
+    ResultMismatch {
+        recorded: Option<Player>,
+        actual: Option<Player>,
+    },
+}
+
+fn result_str(result: Option<Player>) -> &'static str {
+    match result {
+        Some(Player::White) => "1-0",
+        Some(Player::Black) => "0-1",
+        None => "*",
+    }
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::Syntax { line, reason } => write!(f, "line {}: {}", line, reason),
+            RecordError::MissingTag(tag) => write!(f, "missing tag {}", tag),
+            RecordError::Start(e) => write!(f, "invalid starting position: {}", e),
+            RecordError::IllegalMove { turn, mv, error } => {
+                write!(f, "turn {} ({}) is illegal: {}", turn, mv, error)
+            }
+            RecordError::ResultMismatch { recorded, actual } => write!(
+                f,
+                "recorded result {} does not match the final position ({})",
+                result_str(*recorded),
+                result_str(*actual)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+/// Everything needed to reproduce a game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameRecord {
+    pub width: u32,
+    pub height: u32,
+    pub white_name: String,
+    pub black_name: String,
+    pub white_starting: Vec<Coord>,
+    pub black_starting: Vec<Coord>,
+    /// Arrows already on the board at the start
+    pub arrows: Vec<Coord>,
+    pub first_player: Player,
+    pub moves: Vec<Move>,
+    /// The winner, if the game is over
+    pub result: Option<Player>,
+}
+
+impl GameRecord {
+    /// Records the turns played from the given starting position; the result
+    /// is determined by playing them
+    pub fn new<E: AmazonsEngine + Clone>(
+        start: &E,
+        moves: &[Move],
+        white_name: &str,
+        black_name: &str,
+    ) -> Result<Self, RecordError> {
+        let position = Position::from_engine(start);
+        let mut record = GameRecord {
+            width: start.width(),
+            height: start.height(),
+            white_name: white_name.to_string(),
+            black_name: black_name.to_string(),
+            white_starting: position.squares(Cell::Amazon(Player::White)),
+            black_starting: position.squares(Cell::Amazon(Player::Black)),
+            arrows: position.squares(Cell::Arrow),
+            first_player: position.to_move,
+            moves: moves.to_vec(),
+            result: None,
+        };
+        let mut board = start.clone();
+        record.result = play_all(&mut board, moves, &mut |_, _| ())?;
+        Ok(record)
+    }
+
+    /// Plays the recorded turns from the starting position, checking every
+    /// turn and the result with the rules engine
+    pub fn replay<E: AmazonsEngine + Clone>(&self) -> Result<History<E>, RecordError> {
+        let mut board: E = setup(
+            self.width,
+            self.height,
+            &self.white_starting,
+            &self.black_starting,
+            &self.arrows,
+            self.first_player,
+        )
+        .map_err(RecordError::Start)?;
+        let mut history = History::new(board.clone());
+        let actual = play_all(&mut board, &self.moves, &mut |mv, board: &E| {
+            history.push(mv, board.clone())
+        })?;
+        if actual != self.result {
+            return Err(RecordError::ResultMismatch {
+                recorded: self.result,
+                actual,
+            });
+        }
+        Ok(history)
+    }
+}
+
+// Plays the given turns, calling `after` with each turn and the position it
+// leads to, and returns the winner if the game is over. Play may go on after
+// the board is partitioned, as long as the side to move has a legal turn.
+fn play_all<E, F>(
+    board: &mut E,
+    moves: &[Move],
+    after: &mut F,
+) -> Result<Option<Player>, RecordError>
+where
+    E: AmazonsEngine,
+    F: FnMut(Move, &E),
+{
+    for (i, mv) in moves.iter().enumerate() {
+        let illegal = |error| RecordError::IllegalMove {
+            turn: i + 1,
+            mv: *mv,
+            error,
+        };
+        if !board.has_valid_move(board.current_player()) {
+            return Err(illegal(BoardError::GameOver));
+        }
+        mv.play(board).map_err(illegal)?;
+        after(*mv, board);
+    }
+    Ok(board.winner().ok().map(|outcome| outcome.winner))
+}
+
+// Tag values are quoted, so quotes and backslashes are escaped with a
+// backslash; line breaks would end the tag and become spaces
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' | '"' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' | '\r' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.push(chars.next().unwrap_or(c)),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+fn squares(coords: &[Coord]) -> String {
+    coords
+        .iter()
+        .map(Coord::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[Size \"{}x{}\"]", self.width, self.height)?;
+        writeln!(f, "[White \"{}\"]", escape(&self.white_name))?;
+        writeln!(f, "[Black \"{}\"]", escape(&self.black_name))?;
+        writeln!(f, "[WhiteStart \"{}\"]", squares(&self.white_starting))?;
+        writeln!(f, "[BlackStart \"{}\"]", squares(&self.black_starting))?;
+        if !self.arrows.is_empty() {
+            writeln!(f, "[Arrows \"{}\"]", squares(&self.arrows))?;
+        }
+        if self.first_player == Player::Black {
+            writeln!(f, "[ToMove \"b\"]")?;
+        }
+        writeln!(f, "[Result \"{}\"]", result_str(self.result))?;
+        writeln!(f)?;
+        for (i, mv) in self.moves.iter().enumerate() {
+            writeln!(f, "{}. {}", i + 1, mv)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for GameRecord {
+    type Err = RecordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mut size, mut result) = (None, None);
+        let (mut white_name, mut black_name) = (String::new(), String::new());
+        let (mut white_starting, mut black_starting) = (None, None);
+        let (mut arrows, mut first_player) = (vec![], Player::White);
+        let mut moves = vec![];
+        for (i, line) in s.lines().enumerate() {
+            let syntax = |reason: String| RecordError::Syntax {
+                line: i + 1,
+                reason,
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(tag) = line.strip_prefix('[') {
+                let (key, value) = tag
+                    .strip_suffix("\"]")
+                    .and_then(|tag| tag.split_once(" \""))
+                    .ok_or_else(|| syntax("expected a tag such as [Key \"value\"]".to_string()))?;
+                let coords = |value: &str| -> Result<Vec<Coord>, RecordError> {
+                    value
+                        .split_whitespace()
+                        .map(|sq| sq.parse().map_err(|e: ParseError| syntax(e.to_string())))
+                        .collect()
+                };
+                match key {
+                    "Size" => {
+                        let parsed = value
+                            .split_once('x')
+                            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+                        let (width, height): (u32, u32) = parsed.ok_or_else(|| {
+                            syntax(format!("'{}' is not a board size such as 10x10", value))
+                        })?;
+                        if !(1..=MAX_SIZE).contains(&width) || !(1..=MAX_SIZE).contains(&height) {
+                            return Err(syntax(format!(
+                                "board size {} is out of range; width and height must be between 1 and {}",
+                                value, MAX_SIZE
+                            )));
+                        }
+                        size = Some((width, height));
+                    }
+                    "White" => white_name = unescape(value),
+                    "Black" => black_name = unescape(value),
+                    "WhiteStart" => white_starting = Some(coords(value)?),
+                    "BlackStart" => black_starting = Some(coords(value)?),
+                    "Arrows" => arrows = coords(value)?,
+                    "ToMove" => {
+                        first_player = match value {
+                            "w" => Player::White,
+                            "b" => Player::Black,
+                            _ => return Err(syntax(format!("'{}' is not a side to move", value))),
+                        }
+                    }
+                    "Result" => {
+                        result = Some(match value {
+                            "1-0" => Some(Player::White),
+                            "0-1" => Some(Player::Black),
+                            "*" => None,
+                            _ => return Err(syntax(format!("'{}' is not a result", value))),
+                        })
+                    }
+                    // Unknown tags are kept for compatibility with other programs
+                    _ => (),
+                }
+                continue;
+            }
+            for token in line.split_whitespace() {
+                if token.ends_with('.') && token[..token.len() - 1].parse::<usize>().is_ok() {
+                    continue;
+                }
+                let mv = token
+                    .parse()
+                    .map_err(|e: ParseError| syntax(format!("'{}': {}", token, e)))?;
+                moves.push(mv);
+            }
+        }
+        let (width, height) = size.ok_or(RecordError::MissingTag("Size"))?;
+        Ok(GameRecord {
+            width,
+            height,
+            white_name,
+            black_name,
+            white_starting: white_starting.ok_or(RecordError::MissingTag("WhiteStart"))?,
+            black_starting: black_starting.ok_or(RecordError::MissingTag("BlackStart"))?,
+            arrows,
+            first_player,
+            moves,
+            result: result.ok_or(RecordError::MissingTag("Result"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+
+    fn opening() -> Vec<Move> {
+        vec!["d1-d7/g7".parse().unwrap(), "g10-g8/e6".parse().unwrap()]
+    }
+
+    #[test]
+    fn round_trip() {
+        let record = GameRecord::new(&Board::standard(), &opening(), "Alice", "Bob").unwrap();
+        let text = record.to_string();
+        assert!(text.contains("[WhiteStart \"d1 a4 a7 d10\"]"));
+        let parsed: GameRecord = text.parse().unwrap();
+        assert_eq!(parsed, record);
+        let history = parsed.replay::<Board>().unwrap();
+        assert_eq!(history.moves(), &opening()[..]);
+        assert_eq!(history.current().unwrap().current_player(), Player::White);
+    }
+
+    #[test]
+    fn names_are_escaped() {
+        let white = "Alice \"the \\ Queen\"]";
+        let record = GameRecord::new(&Board::standard(), &opening(), white, "Bob\nJr").unwrap();
+        let text = record.to_string();
+        assert!(text.contains("[White \"Alice \\\"the \\\\ Queen\\\"]\"]\n"));
+        let parsed: GameRecord = text.parse().unwrap();
+        assert_eq!(parsed.white_name, white);
+        assert_eq!(parsed.black_name, "Bob Jr");
+        assert_eq!(parsed.moves, record.moves);
+    }
+
+    #[test]
+    fn set_up_start() {
+        let start: Board = "W2/1x1/2B b"
+            .parse::<Position>()
+            .unwrap()
+            .to_engine()
+            .unwrap();
+        let mv: Move = "c3-c2/b3".parse().unwrap();
+        let record = GameRecord::new(&start, &[mv], "", "").unwrap();
+        let text = record.to_string();
+        assert!(text.contains("[Arrows \"b2\"]\n[ToMove \"b\"]"));
+        let history = text
+            .parse::<GameRecord>()
+            .unwrap()
+            .replay::<Board>()
+            .unwrap();
+        assert_eq!(history.current().unwrap().current_player(), Player::White);
+        assert_eq!(
+            history.position(0).unwrap().cell(Coord::new(1, 1)),
+            Ok(Cell::Arrow)
+        );
+    }
+
+    #[test]
+    fn illegal_records_are_rejected() {
+        let record = GameRecord::new(&Board::standard(), &opening(), "", "").unwrap();
+        let text = format!("{}3. d7-d3/e6\n", record);
+        assert_eq!(
+            text.parse::<GameRecord>()
+                .unwrap()
+                .replay::<Board>()
+                .unwrap_err(),
+            RecordError::IllegalMove {
+                turn: 3,
+                mv: "d7-d3/e6".parse().unwrap(),
+                error: BoardError::IllegalShot(Coord::new(3, 2), Coord::new(4, 5)),
+            }
+        );
+        let text = record.to_string().replace("2. g10-g8/e6", "2. g10-g8/z6");
+        assert!(matches!(
+            text.parse::<GameRecord>(),
+            Err(RecordError::Syntax { line: 9, .. })
+        ));
+    }
+
+    #[test]
+    fn size_must_fit_notation() {
+        let record = GameRecord::new(&Board::standard(), &opening(), "", "").unwrap();
+        for size in ["0x10", "10x21", "30x30"] {
+            let text = record
+                .to_string()
+                .replace("[Size \"10x10\"]", &format!("[Size \"{}\"]", size));
+            assert_eq!(
+                text.parse::<GameRecord>().unwrap_err(),
+                RecordError::Syntax {
+                    line: 1,
+                    reason: format!(
+                        "board size {} is out of range; width and height must be between 1 and 20",
+                        size
+                    ),
+                }
+            );
+        }
+        let text = record.to_string().replace("10x10", "20x20");
+        assert_eq!(text.parse::<GameRecord>().unwrap().width, 20);
+    }
+
+    #[test]
+    fn result_must_match() {
+        let board = Board::new(3, 1, &[(0, 0)], &[(2, 0)]).unwrap();
+        let mv: Move = "a1-b1/a1".parse().unwrap();
+        let mut record = GameRecord::new(&board, &[mv], "", "").unwrap();
+        assert_eq!(record.result, Some(Player::White));
+        record.result = None;
+        assert_eq!(
+            record.replay::<Board>().unwrap_err(),
+            RecordError::ResultMismatch {
+                recorded: None,
+                actual: Some(Player::White)
+            }
+        );
+    }
+
+    #[test]
+    fn play_continues_after_partition() {
+        // The arrow on c1 splits the board, but both sides can still move
+        let board = Board::new(5, 1, &[(0, 0)], &[(4, 0)]).unwrap();
+        let moves: Vec<Move> = ["a1-b1/c1", "e1-d1/e1", "b1-a1/b1"]
+            .iter()
+            .map(|mv| mv.parse().unwrap())
+            .collect();
+        let record = GameRecord::new(&board, &moves, "", "").unwrap();
+        assert_eq!(record.result, Some(Player::White));
+        let history = record.replay::<Board>().unwrap();
+        assert_eq!(history.moves(), &moves[..]);
+        // Black is walled in, so no further turn is accepted
+        let mut extra = moves.clone();
+        extra.push("d1-e1/d1".parse().unwrap());
+        assert_eq!(
+            GameRecord::new(&board, &extra, "", "").unwrap_err(),
+            RecordError::IllegalMove {
+                turn: 4,
+                mv: "d1-e1/d1".parse().unwrap(),
+                error: BoardError::GameOver,
+            }
+        );
+    }
+}
@@ -0,0 +1,580 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! A server hosting any number of games between clients speaking the
+//! network protocol of `net`. Every turn is checked against the server's own
+//! board before it is passed on, and a client sending an illegal turn is
+//! disconnected. All clients are served from one thread, which polls their
+//! connections in turn. Messages to a client are queued rather than written
+//! directly, and a client that stops reading is dropped once its queue fills.
+
+use crate::board::Player;
+use crate::engine::AmazonsEngine;
+use crate::movegen::Move;
+use crate::net::{
+    Connection, GameStatus, Listener, LobbyGame, Message, NetError, PROTOCOL_VERSION,
+};
+use crate::position::Position;
+use std::thread;
+use std::time::Duration;
+
+/// Name with which the server introduces itself
+pub const SERVER_NAME: &str = "amazons-server";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Player(Player),
+    Spectator,
+}
+
+struct Client {
+    id: usize,
+    connection: Connection,
+    name: Option<String>,
+    game: Option<(u32, Role)>,
+    /// A message could not be queued, usually because the client stopped
+    /// reading; it is dropped at the end of the step
+    stalled: bool,
+    gone: bool,
+}
+
+struct Game<E> {
+    id: u32,
+    start: E,
+    board: E,
+    moves: Vec<Move>,
+    /// Clients playing white and black
+    players: [Option<usize>; 2],
+    spectators: Vec<usize>,
+}
+
+fn side_index(side: Player) -> usize {
+    match side {
+        Player::White => 0,
+        Player::Black => 1,
+    }
+}
+
+pub struct Server<E> {
+    listener: Listener,
+    clients: Vec<Client>,
+    games: Vec<Game<E>>,
+    next_client: usize,
+    next_game: u32,
+}
+
+impl<E: AmazonsEngine + Clone> Server<E> {
+    pub fn bind(port: u16) -> Result<Self, NetError> {
+        Ok(Server {
+            listener: Listener::bind(port)?,
+            clients: vec![],
+            games: vec![],
+            next_client: 0,
+            next_game: 1,
+        })
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.listener.port()
+    }
+
+    /// Serves clients forever
+    pub fn run(&mut self) {
+        loop {
+            if !self.step() {
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+    }
+
+    /// Accepts new clients and handles the messages that have arrived.
+    /// Returns whether there was anything to do.
+    pub fn step(&mut self) -> bool {
+        let mut busy = false;
+        while let Some(connection) = self.listener.accept() {
+            busy = true;
+            // A client that failed to connect has nothing to clean up
+            if let Ok(connection) = connection {
+                self.clients.push(Client {
+                    id: self.next_client,
+                    connection,
+                    name: None,
+                    game: None,
+                    stalled: false,
+                    gone: false,
+                });
+                self.next_client += 1;
+            }
+        }
+        let mut incoming = vec![];
+        for client in &self.clients {
+            while let Some(message) = client.connection.poll() {
+                let failed = message.is_err();
+                incoming.push((client.id, message));
+                if failed {
+                    break;
+                }
+            }
+        }
+        busy |= !incoming.is_empty();
+        for (client, message) in incoming {
+            if self.client(client).is_none_or(|c| c.gone) {
+                continue;
+            }
+            let result = match message {
+                Ok(Message::Error(_)) | Err(NetError::Closed(_)) => Err(None),
+                Ok(message) => self.handle(client, message).map_err(Some),
+                Err(e) => Err(Some(e.to_string())),
+            };
+            if let Err(reason) = result {
+                if let Some(reason) = reason {
+                    self.send(client, &Message::Error(reason));
+                }
+                self.disconnect(client);
+            }
+        }
+        let stalled: Vec<usize> = self
+            .clients
+            .iter()
+            .filter(|c| c.stalled && !c.gone)
+            .map(|c| c.id)
+            .collect();
+        busy |= !stalled.is_empty();
+        for client in stalled {
+            self.disconnect(client);
+        }
+        self.clients.retain(|client| !client.gone);
+        busy
+    }
+
+    fn client(&self, id: usize) -> Option<&Client> {
+        self.clients.iter().find(|client| client.id == id)
+    }
+
+    fn client_mut(&mut self, id: usize) -> Option<&mut Client> {
+        self.clients.iter_mut().find(|client| client.id == id)
+    }
+
+    fn name(&self, id: usize) -> String {
+        self.client(id)
+            .and_then(|client| client.name.clone())
+            .unwrap_or_else(|| "?".to_string())
+    }
+
+    fn send(&mut self, id: usize, message: &Message) {
+        if let Some(client) = self.client_mut(id) {
+            // Writing never waits, so one slow client cannot hold up the others
+            if client.connection.send(message).is_err() {
+                client.stalled = true;
+            }
+        }
+    }
+
+    fn game(&self, id: u32) -> Option<&Game<E>> {
+        self.games.iter().find(|game| game.id == id)
+    }
+
+    fn game_mut(&mut self, id: u32) -> Option<&mut Game<E>> {
+        self.games.iter_mut().find(|game| game.id == id)
+    }
+
+    fn lobby_game(&self, game: &Game<E>) -> LobbyGame {
+        let mut board = game.board.clone();
+        let status = match game.players {
+            [Some(_), Some(_)] if board.winner().is_ok() => GameStatus::Over,
+            [Some(_), Some(_)] => GameStatus::Playing,
+            _ => GameStatus::Open,
+        };
+        let names: Vec<String> = game
+            .players
+            .iter()
+            .flatten()
+            .map(|&p| self.name(p))
+            .collect();
+        LobbyGame {
+            id: game.id,
+            status,
+            start: Position::from_engine(&game.start),
+            title: names.join(" vs "),
+        }
+    }
+
+    // Handles one message; an error disconnects the client with the reason
+    fn handle(&mut self, client: usize, message: Message) -> Result<(), String> {
+        let named = self.client(client).is_some_and(|c| c.name.is_some());
+        match message {
+            Message::Hello { version, name } => {
+                if version != PROTOCOL_VERSION {
+                    return Err(format!("the server speaks version {}", PROTOCOL_VERSION));
+                }
+                if let Some(c) = self.client_mut(client) {
+                    c.name = Some(name);
+                }
+                self.send(
+                    client,
+                    &Message::Hello {
+                        version,
+                        name: SERVER_NAME.to_string(),
+                    },
+                );
+            }
+            _ if !named => return Err("expected HELLO first".to_string()),
+            Message::List => {
+                let games: Vec<LobbyGame> = self
+                    .games
+                    .iter()
+                    .map(|game| self.lobby_game(game))
+                    .collect();
+                self.send(client, &Message::Lobby(games.len()));
+                for game in games {
+                    self.send(client, &Message::Game(game));
+                }
+            }
+            Message::Create(position) => self.create(client, &position),
+            Message::Join(id) => self.join(client, id),
+            Message::Watch(id) => self.watch(client, id),
+            Message::Move(mv) => self.play(client, mv)?,
+            _ => return Err("unexpected message".to_string()),
+        }
+        Ok(())
+    }
+
+    // Refuses requests from clients that are already in a game
+    fn deny_if_busy(&mut self, client: usize) -> bool {
+        let busy = self.client(client).is_some_and(|c| c.game.is_some());
+        if busy {
+            self.send(client, &Message::Denied("already in a game".to_string()));
+        }
+        busy
+    }
+
+    fn create(&mut self, client: usize, position: &Position) {
+        if self.deny_if_busy(client) {
+            return;
+        }
+        let start: E = match position.to_engine() {
+            Ok(start) => start,
+            Err(e) => {
+                let reason = format!("cannot set up the position: {}", e);
+                return self.send(client, &Message::Denied(reason));
+            }
+        };
+        let id = self.next_game;
+        self.next_game += 1;
+        self.games.push(Game {
+            id,
+            board: start.clone(),
+            start,
+            moves: vec![],
+            players: [Some(client), None],
+            spectators: vec![],
+        });
+        if let Some(c) = self.client_mut(client) {
+            c.game = Some((id, Role::Player(Player::White)));
+        }
+        self.send(client, &Message::Created(id));
+    }
+
+    fn join(&mut self, client: usize, id: u32) {
+        if self.deny_if_busy(client) {
+            return;
+        }
+        let (white, start) = match self.game(id) {
+            Some(game) => match game.players {
+                [Some(white), None] => (white, Position::from_engine(&game.start)),
+                _ => return self.send(client, &Message::Denied("the game is full".to_string())),
+            },
+            None => return self.send(client, &Message::Denied("no such game".to_string())),
+        };
+        if let Some(game) = self.game_mut(id) {
+            game.players[1] = Some(client);
+        }
+        if let Some(c) = self.client_mut(client) {
+            c.game = Some((id, Role::Player(Player::Black)));
+        }
+        // Each player is introduced to the other before the game begins
+        for (to, opponent, side) in [
+            (white, client, Player::White),
+            (client, white, Player::Black),
+        ] {
+            let hello = Message::Hello {
+                version: PROTOCOL_VERSION,
+                name: self.name(opponent),
+            };
+            self.send(to, &hello);
+            let position = start.clone();
+            self.send(
+                to,
+                &Message::Start {
+                    side: Some(side),
+                    position,
+                },
+            );
+        }
+    }
+
+    fn watch(&mut self, client: usize, id: u32) {
+        if self.deny_if_busy(client) {
+            return;
+        }
+        let (title, start, moves) = match self.game(id) {
+            Some(game) => (
+                self.lobby_game(game).title,
+                Position::from_engine(&game.start),
+                game.moves.clone(),
+            ),
+            None => return self.send(client, &Message::Denied("no such game".to_string())),
+        };
+        if let Some(game) = self.game_mut(id) {
+            game.spectators.push(client);
+        }
+        if let Some(c) = self.client_mut(client) {
+            c.game = Some((id, Role::Spectator));
+        }
+        let hello = Message::Hello {
+            version: PROTOCOL_VERSION,
+            name: title,
+        };
+        self.send(client, &hello);
+        let start = Message::Start {
+            side: None,
+            position: start,
+        };
+        self.send(client, &start);
+        for mv in moves {
+            self.send(client, &Message::Move(mv));
+        }
+    }
+
+    fn play(&mut self, client: usize, mv: Move) -> Result<(), String> {
+        let (id, role) = self
+            .client(client)
+            .and_then(|c| c.game)
+            .ok_or("not in a game")?;
+        let side = match role {
+            Role::Player(side) => side,
+            Role::Spectator => return Err("spectators cannot play".to_string()),
+        };
+        let game = self.game_mut(id).ok_or("the game is gone")?;
+        if game.players.iter().any(Option::is_none) {
+            return Err("the game has not started".to_string());
+        }
+        if game.board.current_player() != side {
+            return Err(format!("{} played out of turn", mv));
+        }
+        // The rules engine has the last word on every turn
+        let mut after = game.board.clone();
+        mv.play(&mut after)
+            .map_err(|e| format!("illegal turn {}: {}", mv, e))?;
+        game.board = after;
+        game.moves.push(mv);
+        let opponent = game.players[side_index(side.opponent())];
+        let watchers: Vec<usize> = opponent
+            .into_iter()
+            .chain(game.spectators.clone())
+            .collect();
+        for watcher in watchers {
+            self.send(watcher, &Message::Move(mv));
+        }
+        Ok(())
+    }
+
+    // Removes a client; a game loses its spectators' interest once a player
+    // leaves, so it is closed and everyone still in it is told why
+    fn disconnect(&mut self, client: usize) {
+        let name = self.name(client);
+        let joined = match self.client_mut(client) {
+            Some(c) => {
+                c.gone = true;
+                c.game.take()
+            }
+            None => return,
+        };
+        let (id, role) = match joined {
+            Some(joined) => joined,
+            None => return,
+        };
+        if role == Role::Spectator {
+            if let Some(game) = self.game_mut(id) {
+                game.spectators.retain(|&s| s != client);
+            }
+            return;
+        }
+        let index = match self.games.iter().position(|game| game.id == id) {
+            Some(index) => index,
+            None => return,
+        };
+        let game = self.games.remove(index);
+        let others = game.players.iter().flatten().chain(&game.spectators);
+        for &other in others.filter(|&&other| other != client) {
+            if let Some(c) = self.client_mut(other) {
+                c.game = None;
+            }
+            self.send(other, &Message::Error(format!("{} left the game", name)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+    use crate::net::{Dialer, Event, Session};
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::time::Instant;
+
+    fn connect(server: &mut Server<Board>, name: &str) -> Session<Board> {
+        let dialer = Dialer::connect(&format!("127.0.0.1:{}", server.port().unwrap()));
+        let start = Instant::now();
+        loop {
+            server.step();
+            if let Some(connection) = dialer.try_connection() {
+                return Session::join(connection.unwrap(), name).unwrap();
+            }
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn wait(
+        server: &mut Server<Board>,
+        session: &mut Session<Board>,
+    ) -> Result<Event<Board>, NetError> {
+        let start = Instant::now();
+        loop {
+            server.step();
+            if let Some(result) = session.poll() {
+                return result;
+            }
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn lobby_game_and_spectator() {
+        let mut server = Server::<Board>::bind(0).unwrap();
+        let mut ann = connect(&mut server, "Ann");
+        let mut bo = connect(&mut server, "Bo");
+        let standard = Position::from_engine(&Board::standard());
+        ann.request(&Message::Create(standard.clone())).unwrap();
+        assert!(matches!(wait(&mut server, &mut ann), Ok(Event::Created(1))));
+        bo.request(&Message::List).unwrap();
+        match wait(&mut server, &mut bo) {
+            Ok(Event::Lobby(games)) => {
+                assert_eq!(games.len(), 1);
+                assert_eq!(
+                    (games[0].status, games[0].title.as_str()),
+                    (GameStatus::Open, "Ann")
+                );
+                assert_eq!(games[0].start, standard);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        bo.request(&Message::Join(1)).unwrap();
+        assert!(matches!(
+            wait(&mut server, &mut bo),
+            Ok(Event::Started {
+                local: Some(Player::Black),
+                ..
+            })
+        ));
+        assert!(matches!(
+            wait(&mut server, &mut ann),
+            Ok(Event::Started {
+                local: Some(Player::White),
+                ..
+            })
+        ));
+        assert_eq!(bo.peer_name(), Some("Ann"));
+
+        let mv: Move = "d1-d7/g7".parse().unwrap();
+        ann.send_move(mv).unwrap();
+        assert!(matches!(wait(&mut server, &mut bo), Ok(Event::Move(m)) if m == mv));
+
+        // A spectator joining late is sent the turns played so far
+        let mut cy = connect(&mut server, "Cy");
+        cy.request(&Message::Watch(1)).unwrap();
+        assert!(matches!(
+            wait(&mut server, &mut cy),
+            Ok(Event::Started { local: None, .. })
+        ));
+        assert_eq!(cy.peer_name(), Some("Ann vs Bo"));
+        assert!(matches!(wait(&mut server, &mut cy), Ok(Event::Move(m)) if m == mv));
+
+        // The server refuses an illegal turn even if the client lets it through
+        let illegal: Move = "a7-a1/a2".parse().unwrap();
+        bo.request(&Message::Move(illegal)).unwrap();
+        assert!(matches!(
+            wait(&mut server, &mut bo),
+            Err(NetError::Closed(Some(_)))
+        ));
+        assert_eq!(
+            wait(&mut server, &mut ann).err(),
+            Some(NetError::Closed(Some("Bo left the game".to_string())))
+        );
+        assert!(matches!(
+            wait(&mut server, &mut cy),
+            Err(NetError::Closed(Some(_)))
+        ));
+    }
+
+    // Steps the server until the client with the given id has come and gone
+    fn wait_until_dropped(server: &mut Server<Board>, id: usize) {
+        let start = Instant::now();
+        while server.next_client <= id || server.client(id).is_some() {
+            server.step();
+            assert!(start.elapsed() < Duration::from_secs(30));
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn slow_and_misbehaving_clients_are_dropped() {
+        let mut server = Server::<Board>::bind(0).unwrap();
+        // A long name makes every lobby listing large
+        let name = "Ann".repeat(1000);
+        let mut ann = connect(&mut server, &name);
+        let standard = Position::from_engine(&Board::standard());
+        ann.request(&Message::Create(standard)).unwrap();
+        assert!(matches!(wait(&mut server, &mut ann), Ok(Event::Created(1))));
+
+        // This client keeps asking for the lobby but never reads the answers
+        let address = ("127.0.0.1", server.port().unwrap());
+        let id = server.next_client;
+        let mut idle = TcpStream::connect(address).unwrap();
+        thread::spawn(move || {
+            let _ = writeln!(idle, "HELLO 1 Idle");
+            for _ in 0..100_000 {
+                if idle.write_all(b"LIST\n").is_err() {
+                    break;
+                }
+            }
+        });
+        wait_until_dropped(&mut server, id);
+
+        // Nor may a client send a line without end
+        let id = server.next_client;
+        let mut long = TcpStream::connect(address).unwrap();
+        thread::spawn(move || {
+            let _ = long.write_all(format!("HELLO 1 {}\n", "x".repeat(10_000)).as_bytes());
+        });
+        wait_until_dropped(&mut server, id);
+
+        // Everyone else is still served
+        ann.request(&Message::List).unwrap();
+        assert!(matches!(wait(&mut server, &mut ann), Ok(Event::Lobby(games)) if games.len() == 1));
+    }
+}
@@ -0,0 +1,603 @@
+// Copyright (C) 2022 Arc676/Alessandro Vinciguerra <alesvinciguerra@gmail.com>
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation (version 3).
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Smart Game Format files for Amazons (`GM[18]`). Points are written as two
+//! letters for the column and the row, `a` to `z` followed by `A` to `Z`, so
+//! that `(3, 0)` is `da`. A turn is the three points of the starting square,
+//! the destination and the arrow target, e.g. `W[dadggg]` for `d1-d7/g7`.
+//! Custom starting positions use the setup properties `AW` and `AB` for the
+//! amazons and `AA` for arrows, with `PL[B]` if black moves first.
+//!
+//! The whole game tree is kept, including properties this program does not
+//! use, so that variations and comments survive loading and saving a file.
+
+use crate::board::{BoardError, Cell, Coord, Player};
+use crate::engine::AmazonsEngine;
+use crate::movegen::Move;
+use crate::notation::MAX_SIZE;
+use crate::position::{setup, Position};
+use crate::record::{GameRecord, RecordError};
+use std::fmt;
+use std::str::FromStr;
+
+/// Game type number assigned to Amazons
+pub const GAME_TYPE: &str = "18";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SgfError {
+    /// The file is not well-formed SGF; `offset` counts characters
+    Syntax {
+        offset: usize,
+        reason: &'static str,
+    },
+    /// The file records a different game
+    NotAmazons(String),
+    InvalidSize(String),
+    /// The board is larger than move notation allows, or empty
+    SizeOutOfRange(u32, u32),
+    InvalidPoint(String),
+    InvalidMove(String),
+    Start(BoardError),
+    /// The main line is not a legal game
+    Record(RecordError),
+}
+
+impl fmt::Display for SgfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SgfError::Syntax { offset, reason } => {
+                write!(f, "character {}: {}", offset, reason)
+            }
+            SgfError::NotAmazons(gm) => write!(f, "game type {} is not Amazons", gm),
+            SgfError::InvalidSize(size) => write!(f, "'{}' is not a board size", size),
+            SgfError::SizeOutOfRange(w, h) => write!(
+                f,
+                "board size {}x{} is out of range; width and height must be between 1 and {}",
+                w, h, MAX_SIZE
+            ),
+            SgfError::InvalidPoint(point) => write!(f, "'{}' is not a point", point),
+            SgfError::InvalidMove(mv) => write!(f, "'{}' is not a turn", mv),
+            SgfError::Start(e) => write!(f, "invalid starting position: {}", e),
+            SgfError::Record(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SgfError {}
+
+/// A node of the game tree with its properties in file order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SgfNode {
+    pub properties: Vec<(String, Vec<String>)>,
+    /// The first child continues the main line; the others are variations
+    pub children: Vec<SgfNode>,
+}
+
+impl SgfNode {
+    pub fn values(&self, id: &str) -> &[String] {
+        self.properties
+            .iter()
+            .find(|(key, _)| key == id)
+            .map_or(&[], |(_, values)| values)
+    }
+
+    pub fn value(&self, id: &str) -> Option<&str> {
+        self.values(id).first().map(String::as_str)
+    }
+
+    /// Replaces the values of a property, or removes it if `values` is empty
+    pub fn set(&mut self, id: &str, values: Vec<String>) {
+        match self.properties.iter().position(|(key, _)| key == id) {
+            Some(i) if values.is_empty() => {
+                self.properties.remove(i);
+            }
+            Some(i) => self.properties[i].1 = values,
+            None if values.is_empty() => (),
+            None => self.properties.push((id.to_string(), values)),
+        }
+    }
+
+    /// This node followed by the first child of each node
+    pub fn main_line(&self) -> Vec<&SgfNode> {
+        let mut line = vec![self];
+        let mut node = self;
+        while let Some(child) = node.children.first() {
+            line.push(child);
+            node = child;
+        }
+        line
+    }
+
+    fn turn(&self) -> Option<&str> {
+        self.value("W").or_else(|| self.value("B"))
+    }
+}
+
+/// The first game tree of an SGF file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SgfGame {
+    pub root: SgfNode,
+}
+
+fn colour_id(player: Player) -> &'static str {
+    match player {
+        Player::White => "W",
+        Player::Black => "B",
+    }
+}
+
+fn point(coord: Coord) -> String {
+    let letter = |n: u32| match n {
+        0..=25 => (b'a' + n as u8) as char,
+        _ => (b'A' + (n - 26) as u8) as char,
+    };
+    format!("{}{}", letter(coord.x), letter(coord.y))
+}
+
+fn parse_point(s: &str) -> Result<Coord, SgfError> {
+    let number = |c: char| match c {
+        'a'..='z' => Some(c as u32 - 'a' as u32),
+        'A'..='Z' => Some(c as u32 - 'A' as u32 + 26),
+        _ => None,
+    };
+    let mut chars = s.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(x), Some(y), None) => match (number(x), number(y)) {
+            (Some(x), Some(y)) => Ok(Coord::new(x, y)),
+            _ => Err(SgfError::InvalidPoint(s.to_string())),
+        },
+        _ => Err(SgfError::InvalidPoint(s.to_string())),
+    }
+}
+
+fn points(values: &[String]) -> Result<Vec<Coord>, SgfError> {
+    values.iter().map(|value| parse_point(value)).collect()
+}
+
+fn move_value(mv: Move) -> String {
+    format!("{}{}{}", point(mv.from), point(mv.to), point(mv.arrow))
+}
+
+fn parse_move(s: &str) -> Result<Move, SgfError> {
+    let invalid = || SgfError::InvalidMove(s.to_string());
+    if !s.is_ascii() || s.len() != 6 {
+        return Err(invalid());
+    }
+    let coord = |range| parse_point(&s[range]).map_err(|_| invalid());
+    Ok(Move::new(coord(0..2)?, coord(2..4)?, coord(4..6)?))
+}
+
+impl SgfGame {
+    pub fn new(record: &GameRecord) -> Self {
+        let mut game = SgfGame::default();
+        game.update(record);
+        game
+    }
+
+    /// Replaces the game information and the main line with the given
+    /// record. Nodes already in the tree are reused along with their comments;
+    /// where the record departs from the tree, the new turns become the main
+    /// line and the old ones are kept as a variation. Turns past the end of the
+    /// record are also kept as variations, behind an empty node that ends the
+    /// main line. A result already in the file, such as a resignation, is kept
+    /// unless the position contradicts it.
+    pub fn update(&mut self, record: &GameRecord) {
+        let squares = |coords: &[Coord]| coords.iter().map(|c| point(*c)).collect();
+        let text = |s: &str| {
+            if s.is_empty() {
+                vec![]
+            } else {
+                vec![s.to_string()]
+            }
+        };
+        let size = if record.width == record.height {
+            record.width.to_string()
+        } else {
+            format!("{}:{}", record.width, record.height)
+        };
+        let root = &mut self.root;
+        root.set("GM", vec![GAME_TYPE.to_string()]);
+        root.set("FF", vec!["4".to_string()]);
+        root.set("SZ", vec![size]);
+        root.set("PW", text(&record.white_name));
+        root.set("PB", text(&record.black_name));
+        root.set("AW", squares(&record.white_starting));
+        root.set("AB", squares(&record.black_starting));
+        root.set("AA", squares(&record.arrows));
+        root.set(
+            "PL",
+            text(if record.first_player == Player::Black {
+                "B"
+            } else {
+                ""
+            }),
+        );
+        let keep_result = match (record.result, root.value("RE")) {
+            (None, Some(_)) => true,
+            (Some(winner), Some(re)) => re.starts_with(&format!("{}+", colour_id(winner))),
+            (_, None) => false,
+        };
+        if !keep_result {
+            let result = match record.result {
+                Some(winner) => format!("{}+", colour_id(winner)),
+                None => String::new(),
+            };
+            root.set("RE", text(&result));
+        }
+
+        let mut node = root;
+        let mut player = record.first_player;
+        for mv in &record.moves {
+            // Drop the end of a main line cut short by an earlier update
+            node.children.retain(|child| *child != SgfNode::default());
+            let (id, value) = (colour_id(player), move_value(*mv));
+            let child = match node
+                .children
+                .iter()
+                .position(|child| child.value(id) == Some(value.as_str()))
+            {
+                Some(i) => node.children.remove(i),
+                None => {
+                    let mut child = SgfNode::default();
+                    child.set(id, vec![value]);
+                    child
+                }
+            };
+            node.children.insert(0, child);
+            node = &mut node.children[0];
+            player = player.opponent();
+        }
+        node.children.retain(|child| *child != SgfNode::default());
+        if !node.children.is_empty() {
+            node.children.insert(0, SgfNode::default());
+        }
+    }
+
+    /// Reads the starting position and the main line, checking every turn
+    /// with the rules engine. A result given in the file is not checked, since
+    /// other programs also record resignations and time losses.
+    pub fn to_record<E: AmazonsEngine + Clone>(&self) -> Result<GameRecord, SgfError> {
+        let root = &self.root;
+        if let Some(gm) = root.value("GM") {
+            if gm.trim() != GAME_TYPE {
+                return Err(SgfError::NotAmazons(gm.to_string()));
+            }
+        }
+        let size = root.value("SZ").unwrap_or("10");
+        let (width, height) = match size.split_once(':') {
+            Some((w, h)) => (w.trim().parse(), h.trim().parse()),
+            None => (size.trim().parse(), size.trim().parse()),
+        };
+        let (width, height) = match (width, height) {
+            (Ok(w), Ok(h)) => (w, h),
+            _ => return Err(SgfError::InvalidSize(size.to_string())),
+        };
+        if !(1..=MAX_SIZE).contains(&width) || !(1..=MAX_SIZE).contains(&height) {
+            return Err(SgfError::SizeOutOfRange(width, height));
+        }
+        let (mut white, mut black) = (points(root.values("AW"))?, points(root.values("AB"))?);
+        // Without setup properties the game starts from the standard position
+        if white.is_empty() && black.is_empty() && (width, height) == (10, 10) {
+            let standard = Position::from_engine(&E::standard());
+            white = standard.squares(Cell::Amazon(Player::White));
+            black = standard.squares(Cell::Amazon(Player::Black));
+        }
+        let first_player = match root.value("PL") {
+            Some("B") => Player::Black,
+            _ => Player::White,
+        };
+        let arrows = points(root.values("AA"))?;
+        let start: E =
+            setup(width, height, &white, &black, &arrows, first_player).map_err(SgfError::Start)?;
+        let moves = root
+            .main_line()
+            .into_iter()
+            .filter_map(SgfNode::turn)
+            .map(parse_move)
+            .collect::<Result<Vec<_>, _>>()?;
+        GameRecord::new(
+            &start,
+            &moves,
+            root.value("PW").unwrap_or(""),
+            root.value("PB").unwrap_or(""),
+        )
+        .map_err(SgfError::Record)
+    }
+
+    /// Comment on the position reached by playing the given turns from the
+    /// start, following whichever variation contains them
+    pub fn comment(&self, moves: &[Move]) -> Option<&str> {
+        let mut node = &self.root;
+        for mv in moves {
+            node = node
+                .children
+                .iter()
+                .find(|child| child.turn().map(parse_move) == Some(Ok(*mv)))?;
+        }
+        node.value("C")
+    }
+}
+
+fn write_sequence(node: &SgfNode, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, ";")?;
+    for (id, values) in &node.properties {
+        write!(f, "{}", id)?;
+        for value in values {
+            write!(f, "[{}]", value.replace('\\', "\\\\").replace(']', "\\]"))?;
+        }
+    }
+    match node.children.len() {
+        0 => Ok(()),
+        1 => {
+            writeln!(f)?;
+            write_sequence(&node.children[0], f)
+        }
+        _ => {
+            for child in &node.children {
+                writeln!(f)?;
+                write!(f, "(")?;
+                write_sequence(child, f)?;
+                write!(f, ")")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Display for SgfGame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(")?;
+        write_sequence(&self.root, f)?;
+        writeln!(f, ")")
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, reason: &'static str) -> SgfError {
+        SgfError::Syntax {
+            offset: self.pos,
+            reason,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: char, reason: &'static str) -> Result<(), SgfError> {
+        if self.peek() != Some(c) {
+            return Err(self.error(reason));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // Reads a game tree and returns its first node, with the rest of the
+    // sequence and the variations as descendants
+    fn tree(&mut self) -> Result<SgfNode, SgfError> {
+        self.expect('(', "expected '(' to start a game tree")?;
+        let mut sequence = vec![];
+        while self.peek() == Some(';') {
+            self.pos += 1;
+            sequence.push(self.node()?);
+        }
+        if sequence.is_empty() {
+            return Err(self.error("expected ';' to start a node"));
+        }
+        let mut variations = vec![];
+        while self.peek() == Some('(') {
+            variations.push(self.tree()?);
+        }
+        self.expect(')', "expected ')' to end the game tree")?;
+        let mut last = sequence.pop().unwrap_or_default();
+        last.children = variations;
+        while let Some(mut node) = sequence.pop() {
+            node.children = vec![last];
+            last = node;
+        }
+        Ok(last)
+    }
+
+    fn node(&mut self) -> Result<SgfNode, SgfError> {
+        let mut node = SgfNode::default();
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            let start = self.pos;
+            while self
+                .chars
+                .get(self.pos)
+                .is_some_and(|c| c.is_ascii_alphabetic())
+            {
+                self.pos += 1;
+            }
+            // Older versions of the format allow lowercase letters in identifiers
+            let id: String = self.chars[start..self.pos]
+                .iter()
+                .filter(|c| c.is_ascii_uppercase())
+                .collect();
+            let mut values = vec![];
+            while self.peek() == Some('[') {
+                self.pos += 1;
+                values.push(self.value()?);
+            }
+            if values.is_empty() {
+                return Err(self.error("expected a property value"));
+            }
+            node.properties.push((id, values));
+        }
+        Ok(node)
+    }
+
+    fn value(&mut self) -> Result<String, SgfError> {
+        let mut value = String::new();
+        loop {
+            let c = *self
+                .chars
+                .get(self.pos)
+                .ok_or_else(|| self.error("unterminated property value"))?;
+            self.pos += 1;
+            match c {
+                ']' => return Ok(value),
+                '\\' => {
+                    let escaped = *self
+                        .chars
+                        .get(self.pos)
+                        .ok_or_else(|| self.error("unterminated property value"))?;
+                    self.pos += 1;
+                    // An escaped line break is a soft line break and is removed
+                    if escaped != '\n' {
+                        value.push(escaped);
+                    }
+                }
+                c => value.push(c),
+            }
+        }
+    }
+}
+
+impl FromStr for SgfGame {
+    type Err = SgfError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            chars: s.chars().collect(),
+            pos: 0,
+        };
+        Ok(SgfGame {
+            root: parser.tree()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+
+    #[test]
+    fn round_trip() {
+        let start: Board = "W2/1x1/2B b"
+            .parse::<Position>()
+            .unwrap()
+            .to_engine()
+            .unwrap();
+        let mv: Move = "c3-c2/b3".parse().unwrap();
+        let record = GameRecord::new(&start, &[mv], "Alice", "Bob").unwrap();
+        let text = SgfGame::new(&record).to_string();
+        assert!(text.contains("SZ[3]PW[Alice]PB[Bob]AW[aa]AB[cc]AA[bb]PL[B]"));
+        assert!(text.contains(";B[cccbbc]"));
+        let parsed: SgfGame = text.parse().unwrap();
+        assert_eq!(parsed.to_record::<Board>(), Ok(record));
+    }
+
+    #[test]
+    fn variations_and_comments_are_kept() {
+        let text = "(;GM[18]FF[4]SZ[10]C[Opening \\] test]\
+                    ;W[dadggg]C[Main line](;B[gjghef])(;B[jdjfhf]XX[unknown]))";
+        let mut game: SgfGame = text.parse().unwrap();
+        let record = game.to_record::<Board>().unwrap();
+        assert_eq!(record.moves.len(), 2);
+        assert_eq!(record.moves[1], "g10-g8/e6".parse().unwrap());
+        assert_eq!(game.comment(&[]), Some("Opening ] test"));
+        assert_eq!(game.comment(&record.moves[..1]), Some("Main line"));
+        assert_eq!(game.comment(&record.moves), None);
+
+        let mut replaced = record.clone();
+        replaced.moves[1] = "j4-j6/h6".parse().unwrap();
+        game.update(&replaced);
+        let reparsed: SgfGame = game.to_string().parse().unwrap();
+        assert_eq!(reparsed, game);
+        let second = &reparsed.root.children[0];
+        assert_eq!(second.value("C"), Some("Main line"));
+        assert_eq!(second.children[0].value("XX"), Some("unknown"));
+        assert_eq!(second.children[1].value("B"), Some("gjghef"));
+        assert_eq!(reparsed.to_record::<Board>().unwrap().moves, replaced.moves);
+    }
+
+    #[test]
+    fn shorter_record_ends_main_line() {
+        let text = "(;GM[18]FF[4]SZ[10];W[dadggg]C[First];B[gjghef])";
+        let mut game: SgfGame = text.parse().unwrap();
+        let record = game.to_record::<Board>().unwrap();
+        let mut shorter = record.clone();
+        shorter.moves.truncate(1);
+        game.update(&shorter);
+        let reparsed: SgfGame = game.to_string().parse().unwrap();
+        assert_eq!(reparsed, game);
+        assert_eq!(reparsed.to_record::<Board>().unwrap().moves, shorter.moves);
+        assert_eq!(game.comment(&record.moves[..1]), Some("First"));
+
+        // Playing the old turns again brings them back to the main line
+        game.update(&record);
+        let mut expected: SgfGame = text.parse().unwrap();
+        expected.update(&record);
+        assert_eq!(game, expected);
+    }
+
+    #[test]
+    fn recorded_result_is_kept() {
+        let text = "(;GM[18]FF[4]SZ[10]RE[B+R];W[dadggg])";
+        let mut game: SgfGame = text.parse().unwrap();
+        let record = game.to_record::<Board>().unwrap();
+        assert_eq!(record.result, None);
+        game.update(&record);
+        assert_eq!(game.to_string().parse::<SgfGame>().unwrap(), game);
+        assert!(game.to_string().contains("RE[B+R]"));
+
+        // A decided game keeps a result that agrees with it and replaces one that does not
+        let text = "(;GM[18]FF[4]SZ[3:1]AW[aa]AB[ca]RE[W+T];W[aabaaa])";
+        let mut game: SgfGame = text.parse().unwrap();
+        let record = game.to_record::<Board>().unwrap();
+        assert_eq!(record.result, Some(Player::White));
+        game.update(&record);
+        assert_eq!(game.root.value("RE"), Some("W+T"));
+        game.root.set("RE", vec!["B+R".to_string()]);
+        game.update(&record);
+        assert_eq!(game.root.value("RE"), Some("W+"));
+    }
+
+    #[test]
+    fn malformed_files() {
+        assert!(matches!(
+            "(;GM[18];W[dadggg]".parse::<SgfGame>(),
+            Err(SgfError::Syntax { .. })
+        ));
+        let game: SgfGame = "(;GM[1]SZ[19])".parse().unwrap();
+        assert_eq!(
+            game.to_record::<Board>(),
+            Err(SgfError::NotAmazons("1".to_string()))
+        );
+        let game: SgfGame = "(;GM[18];W[dadg])".parse().unwrap();
+        assert_eq!(
+            game.to_record::<Board>(),
+            Err(SgfError::InvalidMove("dadg".to_string()))
+        );
+        for (size, (w, h)) in [("0", (0, 0)), ("21", (21, 21)), ("10:30", (10, 30))] {
+            let game: SgfGame = format!("(;GM[18]SZ[{}])", size).parse().unwrap();
+            assert_eq!(
+                game.to_record::<Board>(),
+                Err(SgfError::SizeOutOfRange(w, h))
+            );
+        }
+        let game: SgfGame = "(;GM[18]SZ[20:1]AW[aa]AB[ta])".parse().unwrap();
+        assert_eq!(game.to_record::<Board>().unwrap().width, 20);
+    }
+}